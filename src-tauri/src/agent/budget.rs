@@ -0,0 +1,154 @@
+// Limits on how much one task may do before it pauses for the user
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::api::types::Usage;
+
+/// Per-task limits. Unset fields are unlimited, except steps which default
+/// to `DEFAULT_MAX_STEPS`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TaskBudget {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_steps: Option<u32>,
+    /// Wall-clock limit of one run of the loop
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_seconds: Option<u64>,
+    /// Prompt plus completion tokens over all requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u64>,
+    /// In CNY; only requests of priced models count
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_cost: Option<f64>,
+}
+
+impl TaskBudget {
+    pub const DEFAULT_MAX_STEPS: u32 = 20;
+
+    /// `self` with every limit `overrides` sets replaced.
+    pub fn overridden_by(&self, overrides: &TaskBudget) -> TaskBudget {
+        TaskBudget {
+            max_steps: overrides.max_steps.or(self.max_steps),
+            max_seconds: overrides.max_seconds.or(self.max_seconds),
+            max_tokens: overrides.max_tokens.or(self.max_tokens),
+            max_cost: overrides.max_cost.or(self.max_cost),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_steps == Some(0) {
+            return Err("max_steps must be at least 1".to_string());
+        }
+        if self.max_seconds == Some(0) {
+            return Err("max_seconds must be at least 1".to_string());
+        }
+        if self.max_tokens == Some(0) {
+            return Err("max_tokens must be at least 1".to_string());
+        }
+        if matches!(self.max_cost, Some(cost) if cost.is_nan() || cost <= 0.0) {
+            return Err("max_cost must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// The limit that stopped a task.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BudgetLimit {
+    Steps,
+    Time,
+    Tokens,
+    Cost,
+}
+
+/// What a task has used of its budget so far.
+pub struct BudgetTracker {
+    budget: TaskBudget,
+    started: Instant,
+    steps: u32,
+    tokens: u64,
+    cost: f64,
+}
+
+impl BudgetTracker {
+    pub fn new(budget: TaskBudget) -> Self {
+        Self {
+            budget,
+            started: Instant::now(),
+            steps: 0,
+            tokens: 0,
+            cost: 0.0,
+        }
+    }
+
+    pub fn step(&mut self) {
+        self.steps += 1;
+    }
+
+    pub fn record(&mut self, usage: &Usage, cost: Option<f64>) {
+        self.tokens += u64::from(usage.prompt_tokens) + u64::from(usage.completion_tokens);
+        self.cost += cost.unwrap_or(0.0);
+    }
+
+    /// When the time limit runs out, if there is one.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.budget.max_seconds.map(|secs| self.started + Duration::from_secs(secs))
+    }
+
+    /// The first limit used up, checked before each request.
+    pub fn exceeded(&self) -> Option<BudgetLimit> {
+        let budget = &self.budget;
+        if self.steps >= budget.max_steps.unwrap_or(TaskBudget::DEFAULT_MAX_STEPS) {
+            Some(BudgetLimit::Steps)
+        } else if matches!(budget.max_seconds, Some(secs) if self.started.elapsed() >= Duration::from_secs(secs)) {
+            Some(BudgetLimit::Time)
+        } else if matches!(budget.max_tokens, Some(tokens) if self.tokens >= tokens) {
+            Some(BudgetLimit::Tokens)
+        } else if matches!(budget.max_cost, Some(cost) if self.cost >= cost) {
+            Some(BudgetLimit::Cost)
+        } else {
+            None
+        }
+    }
+}
+
+/// Completes at `deadline`, or never without one.
+pub async fn until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_limits_override_session_limits() {
+        let session = TaskBudget { max_steps: Some(50), max_cost: Some(1.0), ..Default::default() };
+        let request = TaskBudget { max_steps: Some(5), max_seconds: Some(60), ..Default::default() };
+
+        let budget = session.overridden_by(&request);
+
+        assert_eq!(budget.max_steps, Some(5));
+        assert_eq!(budget.max_seconds, Some(60));
+        assert_eq!(budget.max_cost, Some(1.0));
+        assert!(TaskBudget { max_cost: Some(0.0), ..Default::default() }.validate().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_on_time_and_cost() {
+        let mut tracker = BudgetTracker::new(TaskBudget { max_seconds: Some(30), max_cost: Some(0.5), ..Default::default() });
+        let usage = Usage { prompt_tokens: 1000, completion_tokens: 100, cached_tokens: 0 };
+
+        tracker.record(&usage, Some(0.2));
+        assert_eq!(tracker.exceeded(), None);
+        tracker.record(&usage, Some(0.3));
+        assert_eq!(tracker.exceeded(), Some(BudgetLimit::Cost));
+
+        let tracker = BudgetTracker::new(TaskBudget { max_seconds: Some(30), ..Default::default() });
+        tokio::time::advance(Duration::from_secs(31)).await;
+        assert_eq!(tracker.exceeded(), Some(BudgetLimit::Time));
+    }
+}
@@ -0,0 +1,465 @@
+// Context window budgeting and history compaction
+use crate::api::types::{Message, Tool, Usage};
+use crate::api::LlmBackend;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+/// Per-message framing (role, separators) the providers add to the prompt.
+const MESSAGE_OVERHEAD: usize = 4;
+
+/// Tool results outside the recent window are cut to this many characters.
+const OLD_TOOL_RESULT_CHARS: usize = 2_000;
+
+/// Messages at the end of the history that are never shortened on the first pass.
+const KEEP_RECENT: usize = 6;
+
+/// Each message is cut to this many characters in the transcript sent for summarization.
+const TRANSCRIPT_MESSAGE_CHARS: usize = 2_000;
+
+const SUMMARY_HEADER: &str = "\n\n## 早前对话摘要\n";
+
+const SUMMARY_PROMPT: &str = "你是对话摘要助手。请将下面的对话压缩为一份简洁的摘要，\
+保留：用户的目标与要求、已做出的决定、修改过的文件和关键代码位置、重要的命令输出与错误、尚未完成的事项。\
+省略寒暄和冗余的工具输出。只输出摘要本身。";
+
+/// Rough token count without a tokenizer: CJK and other non-ASCII characters
+/// are about one token each, ASCII text about four characters per token.
+pub fn estimate_tokens(text: &str) -> usize {
+    let (ascii, other) = text.chars().fold((0usize, 0usize), |(a, o), c| if c.is_ascii() { (a + 1, o) } else { (a, o + 1) });
+    ascii.div_ceil(4) + other
+}
+
+pub fn estimate_message(message: &Message) -> usize {
+    let mut tokens = MESSAGE_OVERHEAD + message.content.as_deref().map_or(0, estimate_tokens);
+    for call in message.tool_calls.iter().flatten() {
+        tokens += MESSAGE_OVERHEAD + estimate_tokens(&call.function.name) + estimate_tokens(&call.function.arguments);
+    }
+    tokens
+}
+
+pub fn estimate_history(messages: &[Message]) -> usize {
+    messages.iter().map(estimate_message).sum()
+}
+
+/// Stand-in for the usage of a request the provider reported none for, so
+/// token and cost limits still apply.
+pub fn estimate_usage(prompt_tokens: usize, output: &str) -> Usage {
+    Usage {
+        prompt_tokens: prompt_tokens as u32,
+        completion_tokens: estimate_tokens(output) as u32,
+        cached_tokens: 0,
+    }
+}
+
+/// Tool definitions count against the context window too.
+pub fn estimate_tools(tools: &[Tool]) -> usize {
+    serde_json::to_string(tools).map_or(0, |json| estimate_tokens(&json))
+}
+
+/// Size of the context window the history has to fit in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ContextBudget {
+    pub context_window: u32,
+}
+
+impl ContextBudget {
+    /// Used for models that are not in the catalog.
+    pub const DEFAULT_WINDOW: u32 = 32_768;
+
+    pub fn new(context_window: u32) -> Self {
+        Self { context_window }
+    }
+
+    /// Tokens available to the prompt after reserving room for the answer.
+    pub fn prompt_limit(&self) -> usize {
+        let window = self.context_window as usize;
+        window - (window / 4).min(8_192)
+    }
+}
+
+/// Summary of the first `turns` user turns of a session, persisted so later
+/// tasks can reuse it without asking the model again.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct HistorySummary {
+    pub summary: String,
+    pub turns: u32,
+}
+
+// Keeps the first `head` and last `tail` of `total` characters
+fn cut_middle(text: &str, total: usize, head: usize, tail: usize) -> String {
+    let start: String = text.chars().take(head).collect();
+    let end: String = text.chars().skip(total - tail).collect();
+    format!("{}\n... [{} characters truncated] ...\n{}", start, total - head - tail, end)
+}
+
+/// Keeps the head and tail of overly long text, which usually hold the
+/// command echo and the final error respectively.
+pub fn truncate_middle(text: &str, max_chars: usize) -> String {
+    let total = text.chars().count();
+    if total <= max_chars {
+        return text.to_string();
+    }
+    cut_middle(text, total, max_chars / 2, max_chars / 2)
+}
+
+// Number of leading characters whose estimate stays within `max_tokens`
+fn chars_within(chars: impl Iterator<Item = char>, max_tokens: usize) -> usize {
+    let (mut ascii, mut other, mut count) = (0usize, 0usize, 0);
+    for c in chars {
+        if c.is_ascii() { ascii += 1 } else { other += 1 }
+        if ascii.div_ceil(4) + other > max_tokens {
+            break;
+        }
+        count += 1;
+    }
+    count
+}
+
+/// Like `truncate_middle`, but bounded by estimated tokens rather than characters.
+fn truncate_tokens(text: &str, max_tokens: usize) -> String {
+    if estimate_tokens(text) <= max_tokens {
+        return text.to_string();
+    }
+    let total = text.chars().count();
+    let head = chars_within(text.chars(), max_tokens / 2);
+    let tail = chars_within(text.chars().rev(), max_tokens / 2);
+    cut_middle(text, total, head, tail)
+}
+
+/// Shortens tool results except in the last `keep_recent` messages.
+fn truncate_tool_results(history: &mut [Message], keep_recent: usize, max_chars: usize) {
+    let end = history.len().saturating_sub(keep_recent);
+    for message in &mut history[..end] {
+        if message.role == "tool" {
+            if let Some(content) = &mut message.content {
+                *content = truncate_middle(content, max_chars);
+            }
+        }
+    }
+}
+
+/// Indices of the user messages, each of which starts a turn.
+fn turn_starts(history: &[Message]) -> Vec<usize> {
+    history.iter().enumerate().filter(|(_, m)| m.role == "user").map(|(i, _)| i).collect()
+}
+
+/// Replaces `history[1..end]` with `summary`, which goes into the system prompt.
+/// Providers differ in whether they accept consecutive or extra system
+/// messages, so the summary does not get a message of its own.
+fn replace_with_summary(history: &mut Vec<Message>, end: usize, summary: &str) {
+    if let Some(system) = history.first_mut().filter(|m| m.role == "system") {
+        let prompt = system.content.take().unwrap_or_default();
+        let base = prompt.split(SUMMARY_HEADER).next().unwrap_or_default();
+        system.content = Some(format!("{}{}{}", base, SUMMARY_HEADER, summary));
+        history.drain(1..end);
+    }
+}
+
+/// Renders messages as plain text for the summarization request.
+fn transcript(messages: &[Message]) -> String {
+    let mut out = String::new();
+    for message in messages {
+        let label = match (&message.role[..], &message.name) {
+            ("tool", Some(name)) => format!("tool {}", name),
+            (role, _) => role.to_string(),
+        };
+        if let Some(content) = message.content.as_deref().filter(|c| !c.is_empty()) {
+            out.push_str(&format!("[{}]: {}\n", label, truncate_middle(content, TRANSCRIPT_MESSAGE_CHARS)));
+        }
+        for call in message.tool_calls.iter().flatten() {
+            let args = truncate_middle(&call.function.arguments, TRANSCRIPT_MESSAGE_CHARS);
+            out.push_str(&format!("[{} -> {}]: {}\n", label, call.function.name, args));
+        }
+    }
+    out
+}
+
+/// Keeps the history of one agent task within the context budget.
+pub struct Compactor {
+    budget: ContextBudget,
+    summary: Option<HistorySummary>,
+    // Turns of the original history already replaced by `summary`
+    applied_turns: u32,
+    // Tokens spent on summary requests not yet reported
+    usage: Option<Usage>,
+    // Estimated tokens of summary requests the provider reported no usage for
+    estimated: Option<Usage>,
+}
+
+impl Compactor {
+    pub fn new(budget: ContextBudget, summary: Option<HistorySummary>) -> Self {
+        Self {
+            budget,
+            summary,
+            applied_turns: 0,
+            usage: None,
+            estimated: None,
+        }
+    }
+
+    /// Fits later requests to another model, e.g. after failing over to it.
+    pub fn set_budget(&mut self, budget: ContextBudget) {
+        self.budget = budget;
+    }
+
+    /// Token usage of the summary requests since the last call, to be
+    /// reported like that of any other request.
+    pub fn take_usage(&mut self) -> Option<Usage> {
+        self.usage.take()
+    }
+
+    /// Estimated usage of the summary requests without reported usage since
+    /// the last call; it counts against the task budget only.
+    pub fn take_estimated(&mut self) -> Option<Usage> {
+        self.estimated.take()
+    }
+
+    /// Lowers the budget after the provider rejected a prompt we estimated to fit.
+    pub fn tighten(&mut self) {
+        self.budget.context_window = self.budget.context_window / 4 * 3;
+    }
+
+    /// Shrinks `history` until it fits, cheapest step first: cut old tool
+    /// results, reuse the saved summary, summarize the finished turns, and as
+    /// a last resort cut the tool results of the current turn too.
+    /// Returns the new summary when the model was asked for one.
+    pub async fn fit<B: LlmBackend>(
+        &mut self,
+        history: &mut Vec<Message>,
+        tools_tokens: usize,
+        backend: &B,
+        cancel: &CancellationToken,
+    ) -> Option<HistorySummary> {
+        let limit = self.budget.prompt_limit();
+        let fits = |history: &[Message]| estimate_history(history) + tools_tokens <= limit;
+        if fits(history) {
+            return None;
+        }
+
+        truncate_tool_results(history, KEEP_RECENT, OLD_TOOL_RESULT_CHARS);
+        if fits(history) {
+            return None;
+        }
+
+        if self.applied_turns == 0 {
+            if let Some(saved) = self.summary.clone() {
+                let starts = turn_starts(history);
+                // The saved summary must leave the current turn in place
+                if (saved.turns as usize) < starts.len() {
+                    replace_with_summary(history, starts[saved.turns as usize], &saved.summary);
+                    self.applied_turns = saved.turns;
+                    if fits(history) {
+                        return None;
+                    }
+                }
+            }
+        }
+
+        let mut created = None;
+        let starts = turn_starts(history);
+        if starts.len() > 1 {
+            let end = starts[starts.len() - 1];
+            if let Some(text) = self.summarize(&history[1..end], limit, backend, cancel).await {
+                let summary = HistorySummary {
+                    summary: text,
+                    turns: self.applied_turns + (starts.len() - 1) as u32,
+                };
+                replace_with_summary(history, end, &summary.summary);
+                self.applied_turns = summary.turns;
+                self.summary = Some(summary.clone());
+                created = Some(summary);
+            }
+        }
+
+        if !fits(history) {
+            truncate_tool_results(history, 1, OLD_TOOL_RESULT_CHARS / 4);
+        }
+        created
+    }
+
+    async fn summarize<B: LlmBackend>(
+        &mut self,
+        messages: &[Message],
+        limit: usize,
+        backend: &B,
+        cancel: &CancellationToken,
+    ) -> Option<String> {
+        let mut text = String::new();
+        if self.applied_turns > 0 {
+            if let Some(saved) = &self.summary {
+                text.push_str(&format!("[更早的摘要]: {}\n", saved.summary));
+            }
+        }
+        text.push_str(&transcript(messages));
+        // Leave half of the window for the prompt and the summary itself
+        let text = truncate_tokens(&text, limit / 2);
+
+        let request = vec![
+            Message {
+                role: "system".to_string(),
+                content: Some(SUMMARY_PROMPT.to_string()),
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+                reasoning_content: None,
+            },
+            Message {
+                role: "user".to_string(),
+                content: Some(text),
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+                reasoning_content: None,
+            },
+        ];
+        let prompt_tokens = estimate_history(&request);
+        let response = tokio::select! {
+            _ = cancel.cancelled() => return None,
+            r = backend.chat_completion(request, None) => r,
+        };
+        match response {
+            Ok(response) => {
+                let text = response.choices.into_iter().next().and_then(|c| c.message.content);
+                let (total, usage) = match response.usage {
+                    Some(usage) => (&mut self.usage, usage),
+                    None => (&mut self.estimated, estimate_usage(prompt_tokens, text.as_deref().unwrap_or_default())),
+                };
+                let total = total.get_or_insert_with(Usage::default);
+                total.prompt_tokens += usage.prompt_tokens;
+                total.completion_tokens += usage.completion_tokens;
+                total.cached_tokens += usage.cached_tokens;
+                text.filter(|s| !s.trim().is_empty())
+            },
+            Err(e) => {
+                eprintln!("Failed to summarize history: {}", e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::scripted::{content_chunk, finish_chunk, usage_chunk, ScriptedBackend, ScriptedTurn};
+
+    fn message(role: &str, content: &str) -> Message {
+        Message {
+            role: role.to_string(),
+            content: Some(content.to_string()),
+            tool_calls: None,
+            tool_call_id: None,
+            name: None,
+            reasoning_content: None,
+        }
+    }
+
+    fn turn(question: &str, tool_output: &str) -> Vec<Message> {
+        vec![
+            message("user", question),
+            message("assistant", ""),
+            message("tool", tool_output),
+            message("assistant", "ok"),
+        ]
+    }
+
+    #[test]
+    fn estimates_cjk_and_ascii_text() {
+        assert_eq!(estimate_tokens("abcdefgh"), 2);
+        assert_eq!(estimate_tokens("你好世界"), 4);
+        assert_eq!(estimate_tokens(""), 0);
+    }
+
+    #[tokio::test]
+    async fn truncates_old_tool_results_first() {
+        let backend = ScriptedBackend::new(vec![]);
+        let mut history = vec![message("system", "prompt")];
+        history.extend(turn("read it", &"x".repeat(40_000)));
+        history.extend(turn("again", "small"));
+        history.extend(turn("more", "small"));
+
+        let mut compactor = Compactor::new(ContextBudget::new(8_000), None);
+        let created = compactor.fit(&mut history, 0, &backend, &CancellationToken::new()).await;
+
+        assert!(created.is_none());
+        assert_eq!(backend.requests().len(), 0);
+        assert_eq!(history.len(), 13);
+        assert!(history[3].content.as_ref().unwrap().contains("characters truncated"));
+        assert!(estimate_history(&history) <= ContextBudget::new(8_000).prompt_limit());
+    }
+
+    #[tokio::test]
+    async fn summarizes_finished_turns() {
+        let backend = ScriptedBackend::new(vec![ScriptedBackend::text("用户在重构解析器")]);
+        let mut history = vec![message("system", "prompt")];
+        for i in 0..3 {
+            history.extend(turn(&format!("step {} {}", i, "detail ".repeat(2_000)), "out"));
+        }
+        history.push(message("user", "current"));
+
+        let mut compactor = Compactor::new(ContextBudget::new(4_000), None);
+        let created = compactor.fit(&mut history, 0, &backend, &CancellationToken::new()).await;
+
+        assert_eq!(created, Some(HistorySummary { summary: "用户在重构解析器".to_string(), turns: 3 }));
+        assert_eq!(history.len(), 2);
+        assert!(history[0].content.as_ref().unwrap().ends_with("用户在重构解析器"));
+        assert_eq!(history[1].content.as_deref(), Some("current"));
+    }
+
+    #[tokio::test]
+    async fn reports_usage_of_summary_requests() {
+        let backend = ScriptedBackend::new(vec![ScriptedTurn::Chunks(vec![
+            Ok(content_chunk("摘要")),
+            Ok(finish_chunk("stop")),
+            Ok(usage_chunk(900, 40)),
+        ])]);
+        let mut history = vec![message("system", "prompt")];
+        for i in 0..3 {
+            history.extend(turn(&format!("step {} {}", i, "detail ".repeat(2_000)), "out"));
+        }
+        history.push(message("user", "current"));
+
+        let mut compactor = Compactor::new(ContextBudget::new(4_000), None);
+        assert!(compactor.fit(&mut history, 0, &backend, &CancellationToken::new()).await.is_some());
+        assert_eq!(compactor.take_usage(), Some(Usage { prompt_tokens: 900, completion_tokens: 40, cached_tokens: 0 }));
+        assert_eq!(compactor.take_usage(), None);
+    }
+
+    #[test]
+    fn truncates_transcript_by_tokens() {
+        let cjk = "字".repeat(10_000);
+        let cut = truncate_tokens(&cjk, 1_000);
+        assert!(estimate_tokens(&cut) <= 1_000 + 20);
+        assert!(cut.contains("[9000 characters truncated]"));
+        assert_eq!(truncate_tokens("short", 1_000), "short");
+
+        let ascii = "x".repeat(10_000);
+        assert!(truncate_tokens(&ascii, 1_000).contains("[6000 characters truncated]"));
+    }
+
+    #[tokio::test]
+    async fn reuses_saved_summary_without_asking_the_model() {
+        let backend = ScriptedBackend::new(vec![]);
+        let mut history = vec![message("system", "prompt")];
+        history.extend(turn(&"old ".repeat(20_000), "out"));
+        history.push(message("user", "current"));
+
+        let saved = HistorySummary { summary: "earlier work".to_string(), turns: 1 };
+        let mut compactor = Compactor::new(ContextBudget::new(8_000), Some(saved));
+        let created = compactor.fit(&mut history, 0, &backend, &CancellationToken::new()).await;
+
+        assert!(created.is_none());
+        assert_eq!(backend.requests().len(), 0);
+        assert_eq!(history.len(), 2);
+        assert!(history[0].content.as_ref().unwrap().ends_with("earlier work"));
+    }
+
+    #[tokio::test]
+    async fn leaves_fitting_history_alone() {
+        let backend = ScriptedBackend::new(vec![]);
+        let mut history = vec![message("system", "prompt"), message("user", "hi")];
+        let mut compactor = Compactor::new(ContextBudget::new(ContextBudget::DEFAULT_WINDOW), None);
+        compactor.fit(&mut history, 100, &backend, &CancellationToken::new()).await;
+        assert_eq!(history.len(), 2);
+    }
+}
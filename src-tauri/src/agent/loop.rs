@@ -1,10 +1,73 @@
-use crate::api::deepseek::{DeepSeekClient, Message, ToolCall, FunctionCall};
-use super::registry::ToolRegistry;
+use crate::api::tool_calls::ToolCallAccumulator;
+use crate::api::types::{Message, ToolCall, Usage};
+use crate::api::{models, ApiError, LlmBackend, RetryPolicy};
+use super::budget::{until, BudgetLimit, BudgetTracker, TaskBudget};
+use super::context::{estimate_history, estimate_tools, estimate_usage, Compactor, ContextBudget, HistorySummary};
+use super::permission::{ApprovalHandle, Approvals};
+use super::registry::{ProgressSender, RiskLevel, ToolContext, ToolRegistry, ToolResult};
+use crate::tools::sandbox::Workspace;
+use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use futures::future::join_all;
 use futures::StreamExt;
 
+const CANCELLED_BY_USER: &str = "Task cancelled by user";
+const OUT_OF_TIME: &str = "Stopped: the task reached its time limit";
+
+// Asks the model to pick up an answer that a dropped stream cut off
+const CONTINUE_PROMPT: &str = "你的上一条回复因网络中断被截断。请从中断处继续，不要重复已输出的内容。";
+
+/// Request for resuming an interrupted answer: the history plus the partial answer.
+fn continuation_request(history: &[Message], partial: &str) -> Vec<Message> {
+    let mut request = history.to_vec();
+    for (role, content) in [("assistant", partial), ("user", CONTINUE_PROMPT)] {
+        request.push(Message {
+            role: role.to_string(),
+            content: Some(content.to_string()),
+            tool_calls: None,
+            tool_call_id: None,
+            name: None,
+            reasoning_content: None,
+        });
+    }
+    request
+}
+
+fn model_name<B: LlmBackend>(backend: &B) -> Option<String> {
+    backend.model().map(|(_, model)| model.to_string())
+}
+
+// Model name and cost in CNY of one request's usage
+fn price<B: LlmBackend>(backend: &B, usage: &Usage) -> (String, Option<f64>) {
+    match backend.model() {
+        Some((provider, model)) => (model.to_string(), models::cost(provider, model, usage)),
+        None => (String::new(), None),
+    }
+}
+
+// Provider and model, as shown in the failover notice
+fn describe_backend<B: LlmBackend>(backend: &B) -> String {
+    match backend.model() {
+        Some((provider, model)) => format!("{} ({})", provider.display_name(), model),
+        None => "unknown model".to_string(),
+    }
+}
+
+/// Error text for the user, with a hint on what to do about it.
+fn describe_error(e: &ApiError) -> String {
+    match e {
+        ApiError::Auth(_) => format!("{}. Check the API key and account balance in Settings.", e),
+        ApiError::ContextLengthExceeded(_) => format!("{}. Start a new session or lower the context budget in Settings.", e),
+        ApiError::ContentFiltered(_) => format!("{}. The provider refused to process this content.", e),
+        e if e.is_retryable() => format!("{} (gave up after retries)", e),
+        e => e.to_string(),
+    }
+}
+
 const SYSTEM_PROMPT: &str = r#"你是 CodeMaster，一个专业的 AI 编码助手，专为中国开发者设计。
 
 ## 核心能力
@@ -35,168 +98,411 @@ const SYSTEM_PROMPT: &str = r#"你是 CodeMaster，一个专业的 AI 编码助
 #[derive(Clone, Serialize, Deserialize, Debug)]
 #[serde(tag = "type", content = "content")]
 pub enum AgentEvent {
+    TaskStarted { task_id: String },
     Thinking(String),
     StreamChunk(String),         // New: streaming text chunk
+    Reasoning(String),           // Streaming chain of thought of reasoning models
     StreamEnd,                   // New: streaming ended for current message
     ToolCall { name: String, args: String, id: String },
+    ApprovalRequired { request_id: String, name: String, args: String, id: String, risk: RiskLevel },
+    ToolProgress { id: String, chunk: String },   // Partial output of a running tool
     ToolResult { name: String, result: String, id: String },
+    Usage { model: String, usage: Usage, cost: Option<f64> },   // Tokens used by one request; cost in CNY
+    HistoryCompacted(HistorySummary),   // Earlier turns were summarized to fit the context window
+    BudgetExceeded(BudgetLimit),   // The task stopped on a limit; it can be continued
+    ProviderSwitched { from: String, to: String, reason: String },   // Failover to the next backend of the chain
     Message(String),
-    NewMessage(Message),
+    NewMessage { message: Message, model: Option<String> },   // model: which model produced it
     Error(String),
+    Cancelled,
     Done,
 }
 
-pub struct Agent {
-    client: DeepSeekClient,
+pub struct Agent<B: LlmBackend> {
+    backend: B,
+    // Each with the context budget of its own model
+    fallbacks: Vec<(B, ContextBudget)>,
     registry: Arc<ToolRegistry>,
-    max_steps: u32,
+    budget: TaskBudget,
+    approvals: Option<Arc<Approvals>>,
+    context: ToolContext,
+    context_budget: Option<ContextBudget>,
+    summary: Option<HistorySummary>,
+    retry: RetryPolicy,
 }
 
-impl Agent {
-    pub fn new(client: DeepSeekClient, registry: Arc<ToolRegistry>) -> Self {
+impl<B: LlmBackend> Agent<B> {
+    pub fn new(backend: B, registry: Arc<ToolRegistry>) -> Self {
         Self {
-            client,
+            backend,
+            fallbacks: Vec::new(),
             registry,
-            max_steps: 20,
+            budget: TaskBudget::default(),
+            approvals: None,
+            context: ToolContext::default(),
+            context_budget: None,
+            summary: None,
+            retry: RetryPolicy::default(),
         }
     }
 
-    pub async fn run_task(&self, task: String, mut history: Vec<Message>, tx: mpsc::Sender<AgentEvent>) {
-        // Ensure system prompt is at the beginning
-        let has_system = history.first().map(|m| m.role == "system").unwrap_or(false);
-        if !has_system {
-            history.insert(0, Message {
-                role: "system".to_string(),
-                content: Some(SYSTEM_PROMPT.to_string()),
+    /// Compact the history whenever it outgrows the budget. Without this the
+    /// full history is always sent.
+    pub fn with_context_budget(mut self, budget: ContextBudget) -> Self {
+        self.context_budget = Some(budget);
+        self
+    }
+
+    /// Summary saved by an earlier task of the session, reused when compacting.
+    pub fn with_summary(mut self, summary: Option<HistorySummary>) -> Self {
+        self.summary = summary;
+        self
+    }
+
+    pub fn with_budget(mut self, budget: TaskBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Backends to continue on, in order, once retries on the current one run out.
+    /// A switch also moves compaction to that backend's budget.
+    pub fn with_fallbacks(mut self, fallbacks: Vec<(B, ContextBudget)>) -> Self {
+        self.fallbacks = fallbacks;
+        self
+    }
+
+    pub fn with_session(mut self, session_id: Option<String>) -> Self {
+        self.context.session_id = session_id;
+        self
+    }
+
+    /// Confine tool paths to the workspace root, which also becomes the working
+    /// directory. Without this tools see the whole filesystem from the process cwd.
+    pub fn with_workspace(mut self, workspace: Workspace) -> Self {
+        self.context.cwd = Some(workspace.root().to_path_buf());
+        self.context.workspace = Some(workspace);
+        self
+    }
+
+    pub fn with_env(mut self, env: HashMap<String, String>) -> Self {
+        self.context.env = env;
+        self
+    }
+
+    /// Gate risky tool calls behind user approval. Without this every call runs directly.
+    pub fn with_approvals(mut self, approvals: Arc<Approvals>) -> Self {
+        self.approvals = Some(approvals);
+        self
+    }
+
+    pub async fn run_task(
+        &self,
+        task: String,
+        mut history: Vec<Message>,
+        tx: mpsc::Sender<AgentEvent>,
+        cancel: CancellationToken,
+    ) {
+        if history.is_empty() || history.last().map(|m| m.role.as_str()) != Some("user") {
+             history.push(Message {
+                role: "user".to_string(),
+                content: Some(task),
                 tool_calls: None,
                 tool_call_id: None,
                 name: None,
+                reasoning_content: None,
             });
         }
+        self.run(history, tx, cancel).await;
+    }
 
-        if history.is_empty() || history.last().map(|m| m.role.as_str()) != Some("user") {
-             history.push(Message {
-                role: "user".to_string(),
-                content: Some(task),
+    /// Picks up a task that stopped on its budget, from the history it left behind
+    /// (ending with tool results or the user's message).
+    pub async fn continue_task(
+        &self,
+        history: Vec<Message>,
+        tx: mpsc::Sender<AgentEvent>,
+        cancel: CancellationToken,
+    ) {
+        self.run(history, tx, cancel).await;
+    }
+
+    async fn run(
+        &self,
+        mut history: Vec<Message>,
+        tx: mpsc::Sender<AgentEvent>,
+        cancel: CancellationToken,
+    ) {
+        // Ensure system prompt is at the beginning
+        let has_system = history.first().map(|m| m.role == "system").unwrap_or(false);
+        if !has_system {
+            let system_prompt = match &self.context.cwd {
+                Some(cwd) => format!("{}当前工作目录: {}\n", SYSTEM_PROMPT, cwd.display()),
+                None => SYSTEM_PROMPT.to_string(),
+            };
+            history.insert(0, Message {
+                role: "system".to_string(),
+                content: Some(system_prompt),
                 tool_calls: None,
                 tool_call_id: None,
                 name: None,
+                reasoning_content: None,
             });
         }
 
-        let mut steps = 0;
+        let mut tracker = BudgetTracker::new(self.budget.clone());
+        // Requests and tool calls in flight stop at the time limit too
+        let deadline = tracker.deadline();
         let api_tools = self.registry.to_api_tools();
+        let tools_tokens = estimate_tools(&api_tools);
         let tools_option = if api_tools.is_empty() { None } else { Some(api_tools) };
+        let mut compactor = self.context_budget.map(|budget| Compactor::new(budget, self.summary.clone()));
+        // Index into the failover chain; a switch holds for the rest of the task
+        let mut active = 0;
 
         loop {
-            if steps >= self.max_steps {
-                let _ = tx.send(AgentEvent::Error("Max steps reached".to_string())).await;
+            if cancel.is_cancelled() {
+                let _ = tx.send(AgentEvent::Cancelled).await;
+                break;
+            }
+            if let Some(limit) = tracker.exceeded() {
+                let _ = tx.send(AgentEvent::BudgetExceeded(limit)).await;
                 break;
             }
-            steps += 1;
+            tracker.step();
+
+            if let Some(compactor) = &mut compactor {
+                self.compact(compactor, &mut history, tools_tokens, active, &mut tracker, &cancel, &tx).await;
+            }
             
             let _ = tx.send(AgentEvent::Thinking("Thinking...".to_string())).await;
 
             let current_tools = tools_option.clone();
 
-            // Retry logic for API calls
-            let mut retry_count = 0;
-            const MAX_RETRIES: u32 = 3;
-            
-            let mut stream_result = Err("Initial error".to_string());
-            
-            while retry_count < MAX_RETRIES {
-                match self.client.chat_completion_stream(history.clone(), current_tools.clone()).await {
-                    Ok(s) => {
-                        stream_result = Ok(s);
+            // Accumulated across resumed streams of this step
+            let mut content_buffer = String::new();
+            let mut reasoning_buffer = String::new();
+            let mut pending_calls: ToolCallAccumulator;
+            let mut finish_reason: Option<String>;
+            let mut usage: Option<Usage> = None;
+            let mut failure: Option<ApiError> = None;
+            let mut timed_out = false;
+
+            // Retry transient failures, resuming a stream that died mid-answer;
+            // other errors end the task
+            let mut backoff = self.retry.start();
+            let mut compacted_for_error = false;
+
+            'request: loop {
+                // Half-received tool calls cannot be resumed; the model sends
+                // them again after the text it already streamed
+                pending_calls = ToolCallAccumulator::new();
+                finish_reason = None;
+
+                let request = if content_buffer.is_empty() {
+                    // Starting over, so the model reasons again from scratch
+                    reasoning_buffer.clear();
+                    history.clone()
+                } else {
+                    continuation_request(&history, &content_buffer)
+                };
+                let attempt = tokio::select! {
+                    _ = cancel.cancelled() => break,
+                    _ = until(deadline) => {
+                        timed_out = true;
                         break;
                     },
-                    Err(e) => {
-                        retry_count += 1;
-                        if retry_count < MAX_RETRIES {
-                            let _ = tx.send(AgentEvent::Thinking(format!("Network error, retrying ({}/{})...", retry_count, MAX_RETRIES))).await;
-                            tokio::time::sleep(tokio::time::Duration::from_millis(1000 * retry_count as u64)).await;
-                        } else {
-                            stream_result = Err(e.to_string());
+                    r = self.backend_at(active).chat_completion_stream(request, current_tools.clone()) => r,
+                };
+                let mut stream = match attempt {
+                    Ok(s) => s,
+                    // Our estimate was too optimistic: compact harder once and resend
+                    Err(ApiError::ContextLengthExceeded(_)) if !compacted_for_error && content_buffer.is_empty() => {
+                        compacted_for_error = true;
+                        let _ = tx.send(AgentEvent::Thinking("Context too long, compacting history...".to_string())).await;
+                        let compactor = compactor.get_or_insert_with(|| {
+                            let estimate = estimate_history(&history) + tools_tokens;
+                            Compactor::new(ContextBudget::new(estimate as u32), self.summary.clone())
+                        });
+                        compactor.tighten();
+                        self.compact(compactor, &mut history, tools_tokens, active, &mut tracker, &cancel, &tx).await;
+                        continue;
+                    },
+                    Err(e) => match backoff.next_delay(&e) {
+                        Some(delay) => {
+                            let _ = tx.send(AgentEvent::Thinking(format!(
+                                "{}, retrying ({}/{})...", e, backoff.attempts() - 1, backoff.max_attempts() - 1
+                            ))).await;
+                            tokio::select! {
+                                _ = cancel.cancelled() => break,
+                                _ = until(deadline) => {
+                                    timed_out = true;
+                                    break;
+                                },
+                                _ = tokio::time::sleep(delay) => continue,
+                            }
+                        },
+                        None if e.is_retryable() && self.fail_over(&mut active, &mut compactor, &e, &tx).await => {
+                            backoff = self.retry.start();
+                            continue;
+                        },
+                        None => {
+                            failure = Some(e);
+                            break;
                         }
-                    }
-                }
-            }
-            
-            let mut stream = match stream_result {
-                Ok(s) => s,
-                Err(e) => {
-                    let _ = tx.send(AgentEvent::Error(format!("API Error after retries: {}", e))).await;
-                    break;
-                }
-            };
+                    },
+                };
 
-            // Accumulate streaming response
-            let mut content_buffer = String::new();
-            let mut tool_calls_map: std::collections::HashMap<i32, ToolCall> = std::collections::HashMap::new();
-            let mut _finish_reason: Option<String> = None;
-
-            while let Some(chunk_result) = stream.next().await {
-                match chunk_result {
-                    Ok(chunk) => {
-                        if let Some(choice) = chunk.choices.first() {
-                            // Handle content delta
-                            if let Some(content) = &choice.delta.content {
-                                content_buffer.push_str(content);
-                                let _ = tx.send(AgentEvent::StreamChunk(content.clone())).await;
+                loop {
+                    let chunk_result = tokio::select! {
+                        _ = cancel.cancelled() => break 'request,
+                        _ = until(deadline) => {
+                            timed_out = true;
+                            break 'request;
+                        },
+                        item = stream.next() => match item {
+                            Some(r) => r,
+                            None if finish_reason.is_some() => break 'request,
+                            // A connection closed cleanly mid-answer is still an interruption
+                            None => Err(ApiError::Network("Stream ended before the reply finished".to_string())),
+                        },
+                    };
+                    match chunk_result {
+                        Ok(chunk) => {
+                            if chunk.usage.is_some() {
+                                usage = chunk.usage.clone();
                             }
-                            
-                            // Handle tool calls delta
-                            if let Some(tool_calls) = &choice.delta.tool_calls {
-                                for tc in tool_calls {
-                                    let entry = tool_calls_map.entry(tc.index).or_insert_with(|| ToolCall {
-                                        id: String::new(),
-                                        r#type: "function".to_string(),
-                                        function: FunctionCall {
-                                            name: String::new(),
-                                            arguments: String::new(),
-                                        },
-                                    });
-                                    
-                                    if let Some(id) = &tc.id {
-                                        entry.id = id.clone();
-                                    }
-                                    if let Some(name) = &tc.function.as_ref().and_then(|f| f.name.clone()) {
-                                        entry.function.name = name.clone();
-                                    }
-                                    if let Some(args) = &tc.function.as_ref().and_then(|f| f.arguments.clone()) {
-                                        entry.function.arguments.push_str(args);
+                            if let Some(choice) = chunk.choices.first() {
+                                // Handle content delta
+                                if let Some(content) = &choice.delta.content {
+                                    content_buffer.push_str(content);
+                                    let _ = tx.send(AgentEvent::StreamChunk(content.clone())).await;
+                                }
+
+                                if let Some(reasoning) = &choice.delta.reasoning_content {
+                                    reasoning_buffer.push_str(reasoning);
+                                    let _ = tx.send(AgentEvent::Reasoning(reasoning.clone())).await;
+                                }
+                                
+                                // Handle tool calls delta
+                                if let Some(tool_calls) = &choice.delta.tool_calls {
+                                    for tc in tool_calls {
+                                        pending_calls.push(tc);
                                     }
                                 }
+                                
+                                if choice.finish_reason.is_some() {
+                                    finish_reason = choice.finish_reason.clone();
+                                }
                             }
-                            
-                            if choice.finish_reason.is_some() {
-                                _finish_reason = choice.finish_reason.clone();
+                        },
+                        Err(e) => match backoff.next_delay(&e) {
+                            Some(delay) => {
+                                let _ = tx.send(AgentEvent::Thinking(format!("Stream interrupted ({}), resuming...", e))).await;
+                                tokio::select! {
+                                    _ = cancel.cancelled() => break 'request,
+                                    _ = until(deadline) => {
+                                        timed_out = true;
+                                        break 'request;
+                                    },
+                                    _ = tokio::time::sleep(delay) => continue 'request,
+                                }
+                            },
+                            None if e.is_retryable() && self.fail_over(&mut active, &mut compactor, &e, &tx).await => {
+                                backoff = self.retry.start();
+                                continue 'request;
+                            },
+                            None => {
+                                failure = Some(e);
+                                break 'request;
                             }
-                        }
-                    },
-                    Err(e) => {
-                        let _ = tx.send(AgentEvent::Error(format!("Stream Error: {}", e))).await;
-                        break;
+                        },
                     }
                 }
             }
 
+            // A reply whose tool calls cannot be assembled is as broken as a failed request
+            let stopped = cancel.is_cancelled() || timed_out;
+            let tool_calls = if stopped || failure.is_some() {
+                Vec::new()
+            } else {
+                pending_calls.finish().unwrap_or_else(|e| {
+                    failure = Some(e);
+                    Vec::new()
+                })
+            };
+
             let _ = tx.send(AgentEvent::StreamEnd).await;
 
+            if let Some(usage) = usage {
+                let (model, cost) = price(self.backend_at(active), &usage);
+                tracker.record(&usage, cost);
+                let _ = tx.send(AgentEvent::Usage { model, usage, cost }).await;
+            } else if !content_buffer.is_empty() || !reasoning_buffer.is_empty() || !tool_calls.is_empty() {
+                // The provider did not report usage; estimate it for the budget only
+                let mut output = format!("{}{}", content_buffer, reasoning_buffer);
+                for call in &tool_calls {
+                    output.push_str(&call.function.arguments);
+                }
+                let usage = estimate_usage(estimate_history(&history) + tools_tokens, &output);
+                let (_, cost) = price(self.backend_at(active), &usage);
+                tracker.record(&usage, cost);
+            }
+
+            if stopped || failure.is_some() {
+                // Keep whatever text was streamed, but drop half-received tool calls
+                if !content_buffer.is_empty() || !reasoning_buffer.is_empty() {
+                    let message = Message {
+                        role: "assistant".to_string(),
+                        content: Some(content_buffer),
+                        tool_calls: None,
+                        tool_call_id: None,
+                        name: None,
+                        reasoning_content: Some(reasoning_buffer).filter(|r| !r.is_empty()),
+                    };
+                    let _ = tx.send(AgentEvent::NewMessage { message, model: model_name(self.backend_at(active)) }).await;
+                }
+                let _ = match failure {
+                    Some(e) => tx.send(AgentEvent::Error(describe_error(&e))).await,
+                    None if timed_out && !cancel.is_cancelled() => tx.send(AgentEvent::BudgetExceeded(BudgetLimit::Time)).await,
+                    None => tx.send(AgentEvent::Cancelled).await,
+                };
+                break;
+            }
+
+            // The provider's filter cut the answer off; keep the text but not half-sent tool calls
+            if matches!(finish_reason.as_deref(), Some("content_filter") | Some("sensitive")) {
+                if !content_buffer.is_empty() {
+                    let message = Message {
+                        role: "assistant".to_string(),
+                        content: Some(content_buffer),
+                        tool_calls: None,
+                        tool_call_id: None,
+                        name: None,
+                        reasoning_content: Some(reasoning_buffer).filter(|r| !r.is_empty()),
+                    };
+                    let _ = tx.send(AgentEvent::NewMessage { message, model: model_name(self.backend_at(active)) }).await;
+                }
+                let filtered = ApiError::ContentFiltered("the answer was stopped by the provider's content filter".to_string());
+                let _ = tx.send(AgentEvent::Error(describe_error(&filtered))).await;
+                break;
+            }
+
             // Build complete message
-            let tool_calls_vec: Vec<ToolCall> = tool_calls_map.into_values().collect();
             let message = Message {
                 role: "assistant".to_string(),
                 // Ensure content is never null (use empty string for tool calls)
                 content: Some(content_buffer.clone()), 
-                tool_calls: if tool_calls_vec.is_empty() { None } else { Some(tool_calls_vec.clone()) },
+                tool_calls: if tool_calls.is_empty() { None } else { Some(tool_calls) },
                 tool_call_id: None,
                 name: None,
+                reasoning_content: Some(reasoning_buffer).filter(|r| !r.is_empty()),
             };
             
             // Sync state with frontend
-            let _ = tx.send(AgentEvent::NewMessage(message.clone())).await;
+            let _ = tx.send(AgentEvent::NewMessage {
+                message: message.clone(),
+                model: model_name(self.backend_at(active)),
+            }).await;
             
             history.push(message.clone());
 
@@ -206,44 +512,40 @@ impl Agent {
                     break;
                 }
 
-                for tool_call in tool_calls {
-                    let _ = tx.send(AgentEvent::ToolCall { 
-                        name: tool_call.function.name.clone(), 
-                        args: tool_call.function.arguments.clone(),
-                        id: tool_call.id.clone()
-                    }).await;
-
-                    let tool_name = &tool_call.function.name;
-                    let args_str = &tool_call.function.arguments;
-                    
-                    let result = match self.registry.get(tool_name) {
-                        Some(tool) => {
-                            match serde_json::from_str::<serde_json::Value>(args_str) {
-                                Ok(args) => tool.call(args).await,
-                                Err(e) => Err(format!("Invalid JSON args: {}", e)),
-                            }
-                        },
-                        None => Err(format!("Tool not found: {}", tool_name)),
-                    };
+                // Results go into history in call order, whatever order they finish in
+                for batch in self.batches(tool_calls) {
+                    for tool_call in batch {
+                        let _ = tx.send(AgentEvent::ToolCall { 
+                            name: tool_call.function.name.clone(), 
+                            args: tool_call.function.arguments.clone(),
+                            id: tool_call.id.clone()
+                        }).await;
+                    }
 
-                    let result_str = match result {
-                        Ok(s) => s,
-                        Err(e) => format!("Error: {}", e),
-                    };
+                    let results = join_all(batch.iter().map(|tool_call| self.run_tool_call(tool_call, &tx, &cancel, deadline))).await;
 
-                    let _ = tx.send(AgentEvent::ToolResult { 
-                        name: tool_name.clone(), 
-                        result: result_str.clone(),
-                        id: tool_call.id.clone()
-                    }).await;
+                    for (tool_call, result_str) in batch.iter().zip(results) {
+                        let tool_name = &tool_call.function.name;
+                        let _ = tx.send(AgentEvent::ToolResult { 
+                            name: tool_name.clone(), 
+                            result: result_str.clone(),
+                            id: tool_call.id.clone()
+                        }).await;
 
-                    history.push(Message {
-                        role: "tool".to_string(),
-                        content: Some(result_str),
-                        tool_calls: None,
-                        tool_call_id: Some(tool_call.id.clone()),
-                        name: Some(tool_name.clone()),
-                    });
+                        history.push(Message {
+                            role: "tool".to_string(),
+                            content: Some(result_str),
+                            tool_calls: None,
+                            tool_call_id: Some(tool_call.id.clone()),
+                            name: Some(tool_name.clone()),
+                            reasoning_content: None,
+                        });
+                    }
+                }
+
+                if cancel.is_cancelled() {
+                    let _ = tx.send(AgentEvent::Cancelled).await;
+                    break;
                 }
             } else {
                 break;
@@ -252,4 +554,916 @@ impl Agent {
         
         let _ = tx.send(AgentEvent::Done).await;
     }
+
+    /// Splits the calls of one reply into batches that run one after another:
+    /// each run of concurrency-safe calls forms one batch, every other call its own.
+    fn batches<'a>(&self, tool_calls: &'a [ToolCall]) -> Vec<&'a [ToolCall]> {
+        // Unknown tools only produce an error, so they never need to wait
+        let is_safe = |call: &ToolCall| {
+            self.registry
+                .get(&call.function.name)
+                .map(|tool| tool.is_concurrency_safe())
+                .unwrap_or(true)
+        };
+        let mut batches = Vec::new();
+        let mut start = 0;
+        for (i, call) in tool_calls.iter().enumerate() {
+            if !is_safe(call) {
+                if start < i {
+                    batches.push(&tool_calls[start..i]);
+                }
+                batches.push(&tool_calls[i..=i]);
+                start = i + 1;
+            }
+        }
+        if start < tool_calls.len() {
+            batches.push(&tool_calls[start..]);
+        }
+        batches
+    }
+
+    // Runs one call to its result text. Every tool call still gets a result so the
+    // history stays valid for the next request, even when the task was cancelled.
+    async fn run_tool_call(
+        &self,
+        tool_call: &ToolCall,
+        tx: &mpsc::Sender<AgentEvent>,
+        cancel: &CancellationToken,
+        deadline: Option<Instant>,
+    ) -> String {
+        let result = if cancel.is_cancelled() {
+            Err(CANCELLED_BY_USER.to_string())
+        } else {
+            // Cancellation may drop the tool future at any await point, so
+            // tools must clean up on drop (BashTool forgets and kills its shell).
+            tokio::select! {
+                _ = cancel.cancelled() => Err(CANCELLED_BY_USER.to_string()),
+                _ = until(deadline) => Err(OUT_OF_TIME.to_string()),
+                r = self.execute_tool(tool_call, tx, cancel) => r,
+            }
+        };
+        match result {
+            Ok(s) => s,
+            Err(e) => format!("Error: {}", e),
+        }
+    }
+
+    async fn execute_tool(
+        &self,
+        tool_call: &ToolCall,
+        tx: &mpsc::Sender<AgentEvent>,
+        cancel: &CancellationToken,
+    ) -> ToolResult {
+        let tool_name = &tool_call.function.name;
+        let tool = self.registry.get(tool_name).ok_or_else(|| format!("Tool not found: {}", tool_name))?;
+        let args = serde_json::from_str::<serde_json::Value>(&tool_call.function.arguments)
+            .map_err(|e| format!("Invalid JSON args: {}", e))?;
+
+        let mut ctx = self.context.clone();
+        ctx.cancel = cancel.clone();
+        ctx.progress = Some(ProgressSender::new(tool_call.id.clone(), tx.clone()));
+        ctx.approval = self.approvals.as_ref().map(|approvals| {
+            ApprovalHandle::new(approvals.clone(), ctx.session_id.clone(), tx.clone())
+        });
+
+        if let Some(approval) = &ctx.approval {
+            approval.check(&tool_call.id, tool_name, &args, tool.risk_level()).await?;
+        }
+
+        tool.call(args, ctx).await
+    }
+
+    fn backend_at(&self, index: usize) -> &B {
+        match index {
+            0 => &self.backend,
+            i => &self.fallbacks[i - 1].0,
+        }
+    }
+
+    // Fits the history to the context budget, reporting the summary request's usage and result
+    #[allow(clippy::too_many_arguments)]
+    async fn compact(
+        &self,
+        compactor: &mut Compactor,
+        history: &mut Vec<Message>,
+        tools_tokens: usize,
+        active: usize,
+        tracker: &mut BudgetTracker,
+        cancel: &CancellationToken,
+        tx: &mpsc::Sender<AgentEvent>,
+    ) {
+        let summary = compactor.fit(history, tools_tokens, self.backend_at(active), cancel).await;
+        if let Some(usage) = compactor.take_usage() {
+            let (model, cost) = price(self.backend_at(active), &usage);
+            tracker.record(&usage, cost);
+            let _ = tx.send(AgentEvent::Usage { model, usage, cost }).await;
+        }
+        if let Some(usage) = compactor.take_estimated() {
+            tracker.record(&usage, price(self.backend_at(active), &usage).1);
+        }
+        if let Some(summary) = summary {
+            let _ = tx.send(AgentEvent::HistoryCompacted(summary)).await;
+        }
+    }
+
+    // Switches to the next backend of the chain, if any, and tells the frontend
+    async fn fail_over(
+        &self,
+        active: &mut usize,
+        compactor: &mut Option<Compactor>,
+        error: &ApiError,
+        tx: &mpsc::Sender<AgentEvent>,
+    ) -> bool {
+        if *active >= self.fallbacks.len() {
+            return false;
+        }
+        let from = describe_backend(self.backend_at(*active));
+        *active += 1;
+        let to = describe_backend(self.backend_at(*active));
+        // Only a configured budget follows the model; one guessed after a
+        // context error keeps its estimate
+        if let (Some(compactor), Some(_)) = (compactor.as_mut(), self.context_budget) {
+            compactor.set_budget(self.fallbacks[*active - 1].1);
+        }
+        let _ = tx.send(AgentEvent::ProviderSwitched { from, to, reason: error.to_string() }).await;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::registry::Tool;
+    use crate::agent::permission::{ApprovalResponse, RememberRule};
+    use crate::db::Database;
+    use crate::api::scripted::{content_chunk, finish_chunk, reasoning_chunk, tool_call_chunk, usage_chunk, ScriptedBackend, ScriptedTurn};
+    use crate::api::types::FunctionCall;
+    use crate::api::ModelProvider;
+    use serde_json::{json, Value};
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::time::Duration;
+
+    struct EchoTool;
+
+    impl Tool for EchoTool {
+        fn name(&self) -> &str {
+            "echo"
+        }
+
+        fn description(&self) -> &str {
+            "Echo the text argument"
+        }
+
+        fn parameters(&self) -> Value {
+            json!({ "type": "object", "properties": { "text": { "type": "string" } } })
+        }
+
+        fn risk_level(&self) -> RiskLevel {
+            RiskLevel::ReadOnly
+        }
+
+        fn call(&self, args: Value, _ctx: ToolContext) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
+            Box::pin(async move {
+                args["text"].as_str().map(|s| format!("echo: {}", s)).ok_or_else(|| "Missing text".to_string())
+            })
+        }
+    }
+
+    struct HangTool;
+
+    impl Tool for HangTool {
+        fn name(&self) -> &str {
+            "hang"
+        }
+
+        fn description(&self) -> &str {
+            "Never finishes"
+        }
+
+        fn parameters(&self) -> Value {
+            json!({ "type": "object", "properties": {} })
+        }
+
+        fn risk_level(&self) -> RiskLevel {
+            RiskLevel::Execute
+        }
+
+        fn call(&self, _args: Value, _ctx: ToolContext) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
+            Box::pin(std::future::pending())
+        }
+    }
+
+    struct TouchTool;
+
+    impl Tool for TouchTool {
+        fn name(&self) -> &str {
+            "touch"
+        }
+
+        fn description(&self) -> &str {
+            "Pretends to modify a file"
+        }
+
+        fn parameters(&self) -> Value {
+            json!({ "type": "object", "properties": {} })
+        }
+
+        fn risk_level(&self) -> RiskLevel {
+            RiskLevel::Write
+        }
+
+        fn call(&self, _args: Value, _ctx: ToolContext) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
+            Box::pin(async { Ok("touched".to_string()) })
+        }
+    }
+
+    struct SleepTool;
+
+    impl Tool for SleepTool {
+        fn name(&self) -> &str {
+            "sleep"
+        }
+
+        fn description(&self) -> &str {
+            "Waits for the given milliseconds"
+        }
+
+        fn parameters(&self) -> Value {
+            json!({ "type": "object", "properties": { "ms": { "type": "integer" } } })
+        }
+
+        fn risk_level(&self) -> RiskLevel {
+            RiskLevel::ReadOnly
+        }
+
+        fn call(&self, args: Value, _ctx: ToolContext) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
+            let ms = args["ms"].as_u64().unwrap_or(0);
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(ms)).await;
+                Ok(format!("slept {}", ms))
+            })
+        }
+    }
+
+    fn agent(turns: Vec<ScriptedTurn>, max_steps: u32) -> (Agent<Arc<ScriptedBackend>>, Arc<ScriptedBackend>) {
+        let backend = Arc::new(ScriptedBackend::new(turns));
+        let mut registry = ToolRegistry::new();
+        registry.register(EchoTool);
+        registry.register(HangTool);
+        registry.register(TouchTool);
+        registry.register(SleepTool);
+        let mut agent = Agent::new(backend.clone(), Arc::new(registry));
+        agent.budget.max_steps = Some(max_steps);
+        (agent, backend)
+    }
+
+    fn echo_call_turn(id: &str, text: &str) -> ScriptedTurn {
+        let args = json!({ "text": text }).to_string();
+        let (head, tail) = args.split_at(args.len() / 2);
+        ScriptedTurn::Chunks(vec![
+            Ok(tool_call_chunk(0, Some(id), Some("echo"), None)),
+            Ok(tool_call_chunk(0, None, None, Some(head))),
+            Ok(tool_call_chunk(0, None, None, Some(tail))),
+            Ok(finish_chunk("tool_calls")),
+        ])
+    }
+
+    async fn run(agent: &Agent<Arc<ScriptedBackend>>, task: &str) -> Vec<AgentEvent> {
+        let (tx, mut rx) = mpsc::channel(1000);
+        agent.run_task(task.to_string(), Vec::new(), tx, CancellationToken::new()).await;
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            events.push(event);
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn streams_plain_text_answer() {
+        let (agent, backend) = agent(
+            vec![ScriptedTurn::Chunks(vec![
+                Ok(content_chunk("你好")),
+                Ok(content_chunk("，世界")),
+                Ok(finish_chunk("stop")),
+            ])],
+            20,
+        );
+
+        let events = run(&agent, "hi").await;
+
+        let streamed: String = events
+            .iter()
+            .filter_map(|e| match e {
+                AgentEvent::StreamChunk(s) => Some(s.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(streamed, "你好，世界");
+        assert!(matches!(events.last(), Some(AgentEvent::Done)));
+
+        let requests = backend.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0][0].role, "system");
+        assert_eq!(requests[0][1].content.as_deref(), Some("hi"));
+    }
+
+    #[tokio::test]
+    async fn reports_usage_of_each_request() {
+        let (agent, _backend) = agent(
+            vec![
+                echo_call_turn("call_1", "ping"),
+                ScriptedTurn::Chunks(vec![
+                    Ok(content_chunk("done")),
+                    Ok(finish_chunk("stop")),
+                    Ok(usage_chunk(120, 8)),
+                ]),
+            ],
+            20,
+        );
+
+        let events = run(&agent, "hi").await;
+
+        let usages: Vec<&Usage> = events
+            .iter()
+            .filter_map(|e| match e {
+                AgentEvent::Usage { usage, .. } => Some(usage),
+                _ => None,
+            })
+            .collect();
+        // The scripted tool-call turn sends no usage
+        assert_eq!(usages, vec![&Usage { prompt_tokens: 120, completion_tokens: 8, cached_tokens: 0 }]);
+    }
+
+    #[tokio::test]
+    async fn keeps_reasoning_apart_from_answer() {
+        let (agent, _backend) = agent(
+            vec![ScriptedTurn::Chunks(vec![
+                Ok(reasoning_chunk("用户在")),
+                Ok(reasoning_chunk("打招呼")),
+                Ok(content_chunk("你好")),
+                Ok(finish_chunk("stop")),
+            ])],
+            20,
+        );
+
+        let events = run(&agent, "hi").await;
+
+        let reasoning: String = events
+            .iter()
+            .filter_map(|e| match e {
+                AgentEvent::Reasoning(s) => Some(s.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(reasoning, "用户在打招呼");
+        assert!(!events.iter().any(|e| matches!(e, AgentEvent::StreamChunk(s) if s.contains("打招呼"))));
+
+        let message = events
+            .iter()
+            .find_map(|e| match e {
+                AgentEvent::NewMessage { message, .. } => Some(message),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(message.content.as_deref(), Some("你好"));
+        assert_eq!(message.reasoning_content.as_deref(), Some("用户在打招呼"));
+    }
+
+    #[tokio::test]
+    async fn dispatches_fragmented_tool_call() {
+        let (agent, backend) = agent(
+            vec![echo_call_turn("call_1", "ping"), ScriptedBackend::text("done")],
+            20,
+        );
+
+        let events = run(&agent, "use echo").await;
+
+        assert!(events.iter().any(|e| matches!(
+            e,
+            AgentEvent::ToolCall { name, args, id } if name == "echo" && args == r#"{"text":"ping"}"# && id == "call_1"
+        )));
+        assert!(events.iter().any(|e| matches!(
+            e,
+            AgentEvent::ToolResult { result, id, .. } if result == "echo: ping" && id == "call_1"
+        )));
+
+        // The second request carries the assistant tool call and its result
+        let second = &backend.requests()[1];
+        let tool_msg = second.last().unwrap();
+        assert_eq!(tool_msg.role, "tool");
+        assert_eq!(tool_msg.tool_call_id.as_deref(), Some("call_1"));
+        assert_eq!(tool_msg.content.as_deref(), Some("echo: ping"));
+        assert_eq!(backend.remaining(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn runs_read_only_calls_concurrently_in_call_order() {
+        let calls = ScriptedTurn::Chunks(
+            [("call_a", 300), ("call_b", 100), ("call_c", 200)]
+                .iter()
+                .enumerate()
+                .map(|(i, (id, ms))| Ok(tool_call_chunk(i as i32, Some(id), Some("sleep"), Some(&json!({ "ms": ms }).to_string()))))
+                .chain([Ok(finish_chunk("tool_calls"))])
+                .collect(),
+        );
+        let (agent, backend) = agent(vec![calls, ScriptedBackend::text("done")], 20);
+
+        let started = tokio::time::Instant::now();
+        run(&agent, "hi").await;
+
+        assert!(started.elapsed() < Duration::from_millis(600));
+        let second = &backend.requests()[1];
+        let assistant = second.iter().find(|m| m.role == "assistant").unwrap();
+        let call_ids: Vec<_> = assistant.tool_calls.as_ref().unwrap().iter().map(|c| c.id.clone()).collect();
+        let result_ids: Vec<_> = second.iter().filter_map(|m| m.tool_call_id.clone()).collect();
+        assert_eq!(call_ids, ["call_a", "call_b", "call_c"]);
+        assert_eq!(result_ids, call_ids);
+    }
+
+    #[test]
+    fn mutating_calls_run_alone() {
+        let (agent, _) = agent(Vec::new(), 20);
+        let call = |name: &str| ToolCall {
+            id: String::new(),
+            r#type: "function".to_string(),
+            function: FunctionCall { name: name.to_string(), arguments: "{}".to_string() },
+        };
+        let calls = vec![call("echo"), call("sleep"), call("touch"), call("echo"), call("touch")];
+
+        let sizes: Vec<_> = agent.batches(&calls).iter().map(|b| b.len()).collect();
+
+        assert_eq!(sizes, vec![2, 1, 1, 1]);
+    }
+
+    #[tokio::test]
+    async fn unknown_tool_is_reported_to_model() {
+        let (agent, backend) = agent(
+            vec![
+                ScriptedTurn::Chunks(vec![
+                    Ok(tool_call_chunk(0, Some("call_1"), Some("missing"), Some("{}"))),
+                    Ok(finish_chunk("tool_calls")),
+                ]),
+                ScriptedBackend::text("ok"),
+            ],
+            20,
+        );
+
+        run(&agent, "go").await;
+
+        let tool_msg = backend.requests()[1].last().cloned().unwrap();
+        assert_eq!(tool_msg.content.as_deref(), Some("Error: Tool not found: missing"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_failed_requests() {
+        let (agent, backend) = agent(
+            vec![
+                ScriptedTurn::Fail(ApiError::Network("connection reset".to_string())),
+                ScriptedTurn::Fail(ApiError::Network("connection reset".to_string())),
+                ScriptedBackend::text("recovered"),
+            ],
+            20,
+        );
+
+        let events = run(&agent, "hi").await;
+
+        assert_eq!(backend.requests().len(), 3);
+        assert!(!events.iter().any(|e| matches!(e, AgentEvent::Error(_))));
+        assert!(events.iter().any(|e| matches!(e, AgentEvent::StreamChunk(s) if s == "recovered")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_after_max_retries() {
+        let (agent, backend) = agent(
+            (0..5).map(|_| ScriptedTurn::Fail(ApiError::from_status(503, "overloaded".to_string(), None))).collect(),
+            20,
+        );
+
+        let events = run(&agent, "hi").await;
+
+        assert_eq!(backend.requests().len(), RetryPolicy::default().max_attempts as usize);
+        assert!(events.iter().any(|e| matches!(e, AgentEvent::Error(msg) if msg.contains("503"))));
+        assert!(matches!(events.last(), Some(AgentEvent::Done)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resumes_stream_that_dies_mid_answer() {
+        let (agent, backend) = agent(
+            vec![
+                ScriptedTurn::Chunks(vec![
+                    Ok(content_chunk("前半段")),
+                    Err(ApiError::Network("connection reset".to_string())),
+                ]),
+                ScriptedBackend::text("，后半段"),
+            ],
+            20,
+        );
+
+        let events = run(&agent, "hi").await;
+
+        let requests = backend.requests();
+        assert_eq!(requests.len(), 2);
+        let resumed = &requests[1][requests[1].len() - 2];
+        assert_eq!(resumed.role, "assistant");
+        assert_eq!(resumed.content.as_deref(), Some("前半段"));
+        assert!(!events.iter().any(|e| matches!(e, AgentEvent::Error(_))));
+        let answer = events.iter().find_map(|e| match e {
+            AgentEvent::NewMessage { message, .. } => message.content.clone(),
+            _ => None,
+        });
+        assert_eq!(answer.as_deref(), Some("前半段，后半段"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resumes_stream_closed_without_finish_reason() {
+        let (agent, backend) = agent(
+            vec![
+                ScriptedTurn::Chunks(vec![Ok(content_chunk("前半段"))]),
+                ScriptedBackend::text("，后半段"),
+            ],
+            20,
+        );
+
+        let events = run(&agent, "hi").await;
+
+        assert_eq!(backend.requests().len(), 2);
+        let answer = events.iter().find_map(|e| match e {
+            AgentEvent::NewMessage { message, .. } => message.content.clone(),
+            _ => None,
+        });
+        assert_eq!(answer.as_deref(), Some("前半段，后半段"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_from_scratch_drops_reasoning_of_failed_attempt() {
+        let (agent, _) = agent(
+            vec![
+                ScriptedTurn::Chunks(vec![
+                    Ok(reasoning_chunk("旧思路")),
+                    Err(ApiError::Network("connection reset".to_string())),
+                ]),
+                ScriptedTurn::Chunks(vec![
+                    Ok(reasoning_chunk("新思路")),
+                    Ok(content_chunk("答案")),
+                    Ok(finish_chunk("stop")),
+                ]),
+            ],
+            20,
+        );
+
+        let events = run(&agent, "hi").await;
+
+        let reasoning = events.iter().find_map(|e| match e {
+            AgentEvent::NewMessage { message, .. } => message.reasoning_content.clone(),
+            _ => None,
+        });
+        assert_eq!(reasoning.as_deref(), Some("新思路"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fails_over_to_next_backend_after_retries() {
+        let attempts = RetryPolicy::default().max_attempts;
+        let primary = Arc::new(
+            ScriptedBackend::new(
+                (0..attempts).map(|_| ScriptedTurn::Fail(ApiError::from_status(503, "overloaded".to_string(), None))).collect(),
+            )
+            .with_model(ModelProvider::DeepSeek, "deepseek-chat"),
+        );
+        let fallback = Arc::new(
+            ScriptedBackend::new(vec![ScriptedBackend::text("来自备用模型")]).with_model(ModelProvider::Qwen, "qwen-plus"),
+        );
+        let agent = Agent::new(primary.clone(), Arc::new(ToolRegistry::new()))
+            .with_fallbacks(vec![(fallback.clone(), ContextBudget::new(ContextBudget::DEFAULT_WINDOW))]);
+
+        let events = run(&agent, "hi").await;
+
+        assert_eq!(primary.requests().len(), attempts as usize);
+        assert_eq!(fallback.requests().len(), 1);
+        assert!(events.iter().any(|e| matches!(
+            e,
+            AgentEvent::ProviderSwitched { from, to, .. } if from.contains("deepseek-chat") && to.contains("qwen-plus")
+        )));
+        assert!(!events.iter().any(|e| matches!(e, AgentEvent::Error(_))));
+        assert!(events.iter().any(|e| matches!(
+            e,
+            AgentEvent::NewMessage { model: Some(model), .. } if model == "qwen-plus"
+        )));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn compacts_for_fallback_budget_after_failover() {
+        let attempts = RetryPolicy::default().max_attempts;
+        let primary = Arc::new(ScriptedBackend::new(
+            (0..attempts).map(|_| ScriptedTurn::Fail(ApiError::from_status(503, "overloaded".to_string(), None))).collect(),
+        ));
+        let fallback = Arc::new(ScriptedBackend::new(vec![
+            echo_call_turn("call_1", "a"),
+            ScriptedTurn::Chunks(vec![Ok(content_chunk("摘要")), Ok(finish_chunk("stop")), Ok(usage_chunk(700, 30))]),
+            ScriptedBackend::text("done"),
+        ]));
+        let mut registry = ToolRegistry::new();
+        registry.register(EchoTool);
+        // Fits the primary's window but not the fallback's
+        let agent = Agent::new(primary, Arc::new(registry))
+            .with_context_budget(ContextBudget::new(1_000_000))
+            .with_fallbacks(vec![(fallback.clone(), ContextBudget::new(16_000))]);
+        let old_turn = |role: &str, content: String| Message {
+            role: role.to_string(),
+            content: Some(content),
+            tool_calls: None,
+            tool_call_id: None,
+            name: None,
+            reasoning_content: None,
+        };
+        let history = vec![old_turn("user", "旧问题".to_string()), old_turn("assistant", "旧".repeat(20_000))];
+
+        let (tx, mut rx) = mpsc::channel(1000);
+        agent.run_task("hi".to_string(), history, tx, CancellationToken::new()).await;
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            events.push(event);
+        }
+
+        assert_eq!(fallback.requests().len(), 3);
+        assert!(events.iter().any(|e| matches!(e, AgentEvent::HistoryCompacted(_))));
+        // The summary request shows up in the usage table like any other
+        assert!(events.iter().any(|e| matches!(
+            e,
+            AgentEvent::Usage { usage, .. } if usage.prompt_tokens == 700 && usage.completion_tokens == 30
+        )));
+    }
+
+    #[tokio::test]
+    async fn auth_errors_are_not_retried() {
+        let (agent, backend) = agent(
+            vec![
+                ScriptedTurn::Fail(ApiError::from_status(401, "invalid api key".to_string(), None)),
+                ScriptedBackend::text("unreachable"),
+            ],
+            20,
+        );
+
+        let events = run(&agent, "hi").await;
+
+        assert_eq!(backend.requests().len(), 1);
+        assert!(events.iter().any(|e| matches!(e, AgentEvent::Error(msg) if msg.contains("Settings"))));
+    }
+
+    #[tokio::test]
+    async fn compacts_and_resends_when_context_is_exceeded() {
+        let (agent, backend) = agent(
+            vec![
+                ScriptedTurn::Fail(ApiError::ContextLengthExceeded("maximum context length".to_string())),
+                ScriptedBackend::text("fits now"),
+            ],
+            20,
+        );
+
+        let events = run(&agent, "hi").await;
+
+        assert_eq!(backend.requests().len(), 2);
+        assert!(!events.iter().any(|e| matches!(e, AgentEvent::Error(_))));
+        assert!(events.iter().any(|e| matches!(e, AgentEvent::StreamChunk(s) if s == "fits now")));
+    }
+
+    #[tokio::test]
+    async fn stops_at_max_steps() {
+        let (agent, backend) = agent(
+            vec![
+                echo_call_turn("call_1", "a"),
+                echo_call_turn("call_2", "b"),
+                echo_call_turn("call_3", "c"),
+            ],
+            2,
+        );
+
+        let events = run(&agent, "loop forever").await;
+
+        assert_eq!(backend.requests().len(), 2);
+        assert_eq!(backend.remaining(), 1);
+        assert!(events.iter().any(|e| matches!(e, AgentEvent::BudgetExceeded(BudgetLimit::Steps))));
+    }
+
+    #[tokio::test]
+    async fn continues_task_stopped_by_token_budget() {
+        let (agent, backend) = agent(
+            vec![
+                ScriptedTurn::Chunks(vec![
+                    Ok(tool_call_chunk(0, Some("call_1"), Some("echo"), Some(r#"{"text":"a"}"#))),
+                    Ok(finish_chunk("tool_calls")),
+                    Ok(usage_chunk(8, 4)),
+                ]),
+                ScriptedBackend::text("done"),
+            ],
+            20,
+        );
+        let agent = agent.with_budget(TaskBudget { max_tokens: Some(10), ..Default::default() });
+
+        let events = run(&agent, "hi").await;
+
+        assert_eq!(backend.requests().len(), 1);
+        assert!(events.iter().any(|e| matches!(e, AgentEvent::BudgetExceeded(BudgetLimit::Tokens))));
+
+        // Rebuild the history the frontend would have saved
+        let mut history = backend.requests()[0].clone();
+        for event in &events {
+            match event {
+                AgentEvent::NewMessage { message, .. } => history.push(message.clone()),
+                AgentEvent::ToolResult { name, result, id } => history.push(Message {
+                    role: "tool".to_string(),
+                    content: Some(result.clone()),
+                    tool_calls: None,
+                    tool_call_id: Some(id.clone()),
+                    name: Some(name.clone()),
+                    reasoning_content: None,
+                }),
+                _ => {},
+            }
+        }
+        let (tx, mut rx) = mpsc::channel(1000);
+        agent.continue_task(history, tx, CancellationToken::new()).await;
+        let mut continued = Vec::new();
+        while let Some(event) = rx.recv().await {
+            continued.push(event);
+        }
+
+        let requests = backend.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].last().unwrap().role, "tool");
+        assert_eq!(requests[1].iter().filter(|m| m.role == "user").count(), 1);
+        assert!(continued.iter().any(|e| matches!(e, AgentEvent::StreamChunk(s) if s == "done")));
+        assert!(!continued.iter().any(|e| matches!(e, AgentEvent::BudgetExceeded(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn time_limit_stops_running_tool() {
+        let (agent, backend) = agent(
+            vec![
+                ScriptedTurn::Chunks(vec![
+                    Ok(tool_call_chunk(0, Some("call_1"), Some("hang"), Some("{}"))),
+                    Ok(finish_chunk("tool_calls")),
+                ]),
+                ScriptedBackend::text("unreachable"),
+            ],
+            20,
+        );
+        let agent = agent.with_budget(TaskBudget { max_seconds: Some(30), ..Default::default() });
+
+        let events = run(&agent, "hi").await;
+
+        assert_eq!(backend.requests().len(), 1);
+        assert!(events.iter().any(|e| matches!(
+            e,
+            AgentEvent::ToolResult { result, .. } if result == &format!("Error: {}", OUT_OF_TIME)
+        )));
+        assert!(events.iter().any(|e| matches!(e, AgentEvent::BudgetExceeded(BudgetLimit::Time))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn time_limit_stops_stalled_stream() {
+        let (agent, _backend) = agent(vec![ScriptedTurn::Stall(vec![content_chunk("partial")])], 20);
+        let agent = agent.with_budget(TaskBudget { max_seconds: Some(30), ..Default::default() });
+
+        let events = run(&agent, "hi").await;
+
+        assert!(events.iter().any(|e| matches!(
+            e,
+            AgentEvent::NewMessage { message, .. } if message.content.as_deref() == Some("partial")
+        )));
+        assert!(events.iter().any(|e| matches!(e, AgentEvent::BudgetExceeded(BudgetLimit::Time))));
+        assert!(!events.iter().any(|e| matches!(e, AgentEvent::Cancelled)));
+    }
+
+    #[tokio::test]
+    async fn estimates_usage_the_provider_does_not_report() {
+        let (agent, backend) = agent(
+            vec![echo_call_turn("call_1", "a"), ScriptedBackend::text("done")],
+            20,
+        );
+        let agent = agent.with_budget(TaskBudget { max_tokens: Some(5), ..Default::default() });
+
+        let events = run(&agent, "hi").await;
+
+        assert_eq!(backend.requests().len(), 1);
+        assert!(events.iter().any(|e| matches!(e, AgentEvent::BudgetExceeded(BudgetLimit::Tokens))));
+        // Estimates only count against the budget; they are not reported as usage
+        assert!(!events.iter().any(|e| matches!(e, AgentEvent::Usage { .. })));
+    }
+
+    #[tokio::test]
+    async fn cancels_running_tool_and_closes_history() {
+        let (agent, backend) = agent(
+            vec![
+                ScriptedTurn::Chunks(vec![
+                    Ok(tool_call_chunk(0, Some("call_1"), Some("hang"), Some("{}"))),
+                    Ok(tool_call_chunk(1, Some("call_2"), Some("hang"), Some("{}"))),
+                    Ok(finish_chunk("tool_calls")),
+                ]),
+                ScriptedBackend::text("unreachable"),
+            ],
+            20,
+        );
+        let cancel = CancellationToken::new();
+        let (tx, mut rx) = mpsc::channel(1000);
+
+        let run = {
+            let cancel = cancel.clone();
+            async move { agent.run_task("go".to_string(), Vec::new(), tx, cancel).await }
+        };
+        let watch = async move {
+            let mut events = Vec::new();
+            while let Some(event) = rx.recv().await {
+                if matches!(&event, AgentEvent::ToolCall { name, .. } if name == "hang") {
+                    cancel.cancel();
+                }
+                events.push(event);
+            }
+            events
+        };
+        let (_, events) = tokio::join!(run, watch);
+
+        let results: Vec<&str> = events
+            .iter()
+            .filter_map(|e| match e {
+                AgentEvent::ToolResult { result, .. } => Some(result.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(results, vec!["Error: Task cancelled by user"; 2]);
+        assert!(events.iter().any(|e| matches!(e, AgentEvent::Cancelled)));
+        assert!(matches!(events.last(), Some(AgentEvent::Done)));
+        assert_eq!(backend.remaining(), 1);
+    }
+
+    fn touch_turn(id: &str) -> ScriptedTurn {
+        ScriptedTurn::Chunks(vec![
+            Ok(tool_call_chunk(0, Some(id), Some("touch"), Some("{}"))),
+            Ok(finish_chunk("tool_calls")),
+        ])
+    }
+
+    /// Runs the agent, answering every approval request with `response`.
+    async fn run_with_approvals(
+        agent: Agent<Arc<ScriptedBackend>>,
+        approvals: Arc<Approvals>,
+        response: ApprovalResponse,
+    ) -> Vec<AgentEvent> {
+        let (tx, mut rx) = mpsc::channel(1000);
+        let run = agent.run_task("go".to_string(), Vec::new(), tx, CancellationToken::new());
+        let watch = async move {
+            let mut events = Vec::new();
+            while let Some(event) = rx.recv().await {
+                if let AgentEvent::ApprovalRequired { request_id, .. } = &event {
+                    approvals.respond(request_id, response.clone()).unwrap();
+                }
+                events.push(event);
+            }
+            events
+        };
+        tokio::join!(run, watch).1
+    }
+
+    #[tokio::test]
+    async fn denied_tool_call_is_not_executed() {
+        let db = Arc::new(Database::open_in_memory().unwrap());
+        let approvals = Arc::new(Approvals::new(db));
+        let (agent, backend) = agent(vec![touch_turn("call_1"), ScriptedBackend::text("ok")], 20);
+        let agent = agent.with_approvals(approvals.clone());
+
+        let events = run_with_approvals(agent, approvals, ApprovalResponse { approved: false, remember: None }).await;
+
+        assert!(events.iter().any(|e| matches!(e, AgentEvent::ApprovalRequired { name, .. } if name == "touch")));
+        let tool_msg = backend.requests()[1].last().cloned().unwrap();
+        assert_eq!(tool_msg.content.as_deref(), Some("Error: User denied permission to run touch"));
+    }
+
+    #[tokio::test]
+    async fn remembered_approval_skips_later_prompts() {
+        let db = Arc::new(Database::open_in_memory().unwrap());
+        let session = db.create_session("test", None).unwrap();
+        let approvals = Arc::new(Approvals::new(db.clone()));
+        let (agent, _backend) = agent(
+            vec![touch_turn("call_1"), touch_turn("call_2"), ScriptedBackend::text("ok")],
+            20,
+        );
+        let agent = agent.with_session(Some(session.id.clone())).with_approvals(approvals.clone());
+
+        let events = run_with_approvals(
+            agent,
+            approvals,
+            ApprovalResponse { approved: true, remember: Some(RememberRule::Tool) },
+        )
+        .await;
+
+        let prompts = events.iter().filter(|e| matches!(e, AgentEvent::ApprovalRequired { .. })).count();
+        assert_eq!(prompts, 1);
+        let results = events.iter().filter(|e| matches!(e, AgentEvent::ToolResult { result, .. } if result == "touched")).count();
+        assert_eq!(results, 2);
+
+        let rules = db.get_permission_rules(&session.id).unwrap();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].tool_name, "touch");
+    }
+
 }
@@ -1,2 +1,5 @@
+pub mod budget;
+pub mod context;
 pub mod r#loop;
+pub mod permission;
 pub mod registry;
@@ -1,17 +1,99 @@
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 use std::future::Future;
+use std::path::PathBuf;
 use std::pin::Pin;
-use crate::api::deepseek::Tool as ApiTool;
-use crate::api::deepseek::ToolFunction;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use super::permission::ApprovalHandle;
+use super::r#loop::AgentEvent;
+use crate::api::types::Tool as ApiTool;
+use crate::api::types::ToolFunction;
+use crate::tools::sandbox::Workspace;
 
 pub type ToolResult = Result<String, String>;
 
+/// Per-task execution context handed to every tool call.
+#[derive(Clone, Default)]
+pub struct ToolContext {
+    pub session_id: Option<String>,
+    /// Directory relative paths are resolved against; the process cwd when unset
+    pub cwd: Option<PathBuf>,
+    /// When set, every path a tool touches must stay inside it
+    pub workspace: Option<Workspace>,
+    /// Extra environment variables for spawned processes
+    pub env: HashMap<String, String>,
+    /// Cancelled when the user stops the task; long-running tools should stop early
+    pub cancel: CancellationToken,
+    /// Reports progress of the current tool call to the frontend
+    pub progress: Option<ProgressSender>,
+    /// Asks the user before risky sub-operations; `None` means approval is disabled
+    pub approval: Option<ApprovalHandle>,
+}
+
+/// Event channel of the running task, bound to one tool call.
+#[derive(Clone)]
+pub struct ProgressSender {
+    tool_call_id: String,
+    tx: mpsc::Sender<AgentEvent>,
+}
+
+impl ProgressSender {
+    pub fn new(tool_call_id: String, tx: mpsc::Sender<AgentEvent>) -> Self {
+        Self { tool_call_id, tx }
+    }
+
+    /// Streams a piece of partial output for the tool call to the frontend.
+    pub async fn chunk(&self, chunk: impl Into<String>) {
+        let _ = self.tx.send(AgentEvent::ToolProgress {
+            id: self.tool_call_id.clone(),
+            chunk: chunk.into(),
+        }).await;
+    }
+}
+
+impl ToolContext {
+    /// Resolves a tool path argument against `cwd` (a missing path means `cwd`
+    /// itself) and enforces the workspace root. All file access goes through here.
+    pub fn resolve_path(&self, path: Option<&str>) -> Result<PathBuf, String> {
+        let base = self.cwd.clone().unwrap_or_else(|| PathBuf::from("."));
+        let joined = match path {
+            Some(p) => base.join(p),
+            None => base,
+        };
+        match &self.workspace {
+            Some(workspace) => workspace.resolve(&joined.to_string_lossy()),
+            None => Ok(joined),
+        }
+    }
+}
+
+/// How much damage a tool can do. Anything above `ReadOnly` needs approval.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum RiskLevel {
+    ReadOnly,
+    Write,
+    Execute,
+}
+
+impl RiskLevel {
+    pub fn requires_approval(&self) -> bool {
+        !matches!(self, RiskLevel::ReadOnly)
+    }
+}
+
 pub trait Tool: Send + Sync {
     fn name(&self) -> &str;
     fn description(&self) -> &str;
     fn parameters(&self) -> Value; // JSON Schema
-    fn call(&self, args: Value) -> Pin<Box<dyn Future<Output = ToolResult> + Send>>;
+    fn risk_level(&self) -> RiskLevel;
+    /// Whether calls may run concurrently with other calls of the same reply.
+    /// Read-only tools are safe by default; anything with side effects runs alone.
+    fn is_concurrency_safe(&self) -> bool {
+        self.risk_level() == RiskLevel::ReadOnly
+    }
+    fn call(&self, args: Value, ctx: ToolContext) -> Pin<Box<dyn Future<Output = ToolResult> + Send>>;
 }
 
 pub struct ToolRegistry {
@@ -44,3 +126,32 @@ impl ToolRegistry {
         }).collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_relative_paths_against_cwd() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("src")).unwrap();
+        let workspace = Workspace::new(dir.path()).unwrap();
+        let ctx = ToolContext {
+            cwd: Some(workspace.root().to_path_buf()),
+            workspace: Some(workspace.clone()),
+            ..Default::default()
+        };
+
+        assert_eq!(ctx.resolve_path(Some("src/lib.rs")).unwrap(), workspace.root().join("src/lib.rs"));
+        assert_eq!(ctx.resolve_path(None).unwrap(), workspace.root());
+        assert!(ctx.resolve_path(Some("../outside.txt")).is_err());
+    }
+
+    #[test]
+    fn unconfined_context_keeps_absolute_paths() {
+        let ctx = ToolContext::default();
+        let abs = std::env::temp_dir().join("x.txt");
+
+        assert_eq!(ctx.resolve_path(Some(abs.to_str().unwrap())).unwrap(), abs);
+    }
+}
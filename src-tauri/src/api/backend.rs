@@ -0,0 +1,75 @@
+// LLM backend abstraction used by the agent loop
+use futures::Stream;
+use std::future::Future;
+use std::pin::Pin;
+
+use super::error::ApiError;
+use super::provider::ModelProvider;
+use super::unified::{ChatResponse, Message, StreamChunk, Tool, UnifiedLLMClient};
+
+pub type ChunkStream = Pin<Box<dyn Stream<Item = Result<StreamChunk, ApiError>> + Send>>;
+pub type BackendFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, ApiError>> + Send + 'a>>;
+
+/// Anything the agent loop can talk to: a real provider client or a test double.
+pub trait LlmBackend: Send + Sync {
+    fn chat_completion(
+        &self,
+        messages: Vec<Message>,
+        tools: Option<Vec<Tool>>,
+    ) -> BackendFuture<'_, ChatResponse>;
+
+    fn chat_completion_stream(
+        &self,
+        messages: Vec<Message>,
+        tools: Option<Vec<Tool>>,
+    ) -> BackendFuture<'_, ChunkStream>;
+
+    /// Provider and model answering requests, used to price token usage.
+    fn model(&self) -> Option<(&ModelProvider, &str)> {
+        None
+    }
+}
+
+impl LlmBackend for UnifiedLLMClient {
+    fn chat_completion(
+        &self,
+        messages: Vec<Message>,
+        tools: Option<Vec<Tool>>,
+    ) -> BackendFuture<'_, ChatResponse> {
+        Box::pin(UnifiedLLMClient::chat_completion(self, messages, tools))
+    }
+
+    fn chat_completion_stream(
+        &self,
+        messages: Vec<Message>,
+        tools: Option<Vec<Tool>>,
+    ) -> BackendFuture<'_, ChunkStream> {
+        Box::pin(UnifiedLLMClient::chat_completion_stream(self, messages, tools))
+    }
+
+    fn model(&self) -> Option<(&ModelProvider, &str)> {
+        Some((self.provider(), self.model_name()))
+    }
+}
+
+impl<B: LlmBackend + ?Sized> LlmBackend for std::sync::Arc<B> {
+    fn chat_completion(
+        &self,
+        messages: Vec<Message>,
+        tools: Option<Vec<Tool>>,
+    ) -> BackendFuture<'_, ChatResponse> {
+        (**self).chat_completion(messages, tools)
+    }
+
+    fn chat_completion_stream(
+        &self,
+        messages: Vec<Message>,
+        tools: Option<Vec<Tool>>,
+    ) -> BackendFuture<'_, ChunkStream> {
+        (**self).chat_completion_stream(messages, tools)
+    }
+
+    fn model(&self) -> Option<(&ModelProvider, &str)> {
+        (**self).model()
+    }
+}
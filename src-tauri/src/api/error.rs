@@ -0,0 +1,199 @@
+// Typed errors of the LLM API layer
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Debug, Clone, Error, PartialEq)]
+pub enum ApiError {
+    /// Bad or missing key, or the account cannot be billed
+    #[error("Authentication failed: {0}")]
+    Auth(String),
+    #[error("Rate limited: {message}")]
+    RateLimited {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    #[error("Context length exceeded: {0}")]
+    ContextLengthExceeded(String),
+    #[error("Server error ({status}): {message}")]
+    ServerError {
+        status: u16,
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    #[error("Network error: {0}")]
+    Network(String),
+    #[error("Parse error: {0}")]
+    Parse(String),
+    #[error("Content filtered: {0}")]
+    ContentFiltered(String),
+    /// Any other rejected request, e.g. an unknown model or invalid parameter
+    #[error("API Error ({status}): {message}")]
+    InvalidRequest { status: u16, message: String },
+    /// Unclassified error reported inside a successful response or stream
+    #[error("Provider error: {0}")]
+    Provider(String),
+}
+
+// Phrases the providers use in error messages and codes, lowercased
+const CONTEXT_LENGTH_HINTS: &[&str] = &[
+    "context_length_exceeded",
+    "context length",
+    "maximum context",
+    "context window",
+    "too many tokens",
+    "prompt is too long",
+    "input length",
+    "exceeds the model",
+];
+
+const CONTENT_FILTER_HINTS: &[&str] = &[
+    "content_filter",
+    "content filter",
+    "data_inspection_failed",
+    "inappropriate content",
+    "sensitive content",
+    "sensitive words",
+    "敏感内容",
+    "敏感词",
+    "内容安全",
+];
+
+const RATE_LIMIT_HINTS: &[&str] = &["rate_limit", "rate limit", "too many requests"];
+
+const AUTH_HINTS: &[&str] = &["invalid_api_key", "invalid api key", "incorrect api key", "authentication", "unauthorized"];
+
+fn mentions(message: &str, hints: &[&str]) -> bool {
+    let message = message.to_lowercase();
+    hints.iter().any(|hint| message.contains(hint))
+}
+
+impl ApiError {
+    /// Classifies a failed HTTP response from its status and error message.
+    pub fn from_status(status: u16, message: String, retry_after: Option<Duration>) -> Self {
+        match status {
+            401..=403 => ApiError::Auth(message),
+            429 => ApiError::RateLimited { message, retry_after },
+            500..=599 => ApiError::ServerError { status, message, retry_after },
+            _ if mentions(&message, CONTEXT_LENGTH_HINTS) => ApiError::ContextLengthExceeded(message),
+            _ if mentions(&message, CONTENT_FILTER_HINTS) => ApiError::ContentFiltered(message),
+            413 => ApiError::ContextLengthExceeded(message),
+            _ => ApiError::InvalidRequest { status, message },
+        }
+    }
+
+    /// Classifies an error reported inside a successful response or stream,
+    /// where only the message and code are available.
+    pub fn from_message(message: String) -> Self {
+        if mentions(&message, CONTEXT_LENGTH_HINTS) {
+            ApiError::ContextLengthExceeded(message)
+        } else if mentions(&message, CONTENT_FILTER_HINTS) {
+            ApiError::ContentFiltered(message)
+        } else if mentions(&message, RATE_LIMIT_HINTS) {
+            ApiError::RateLimited { message, retry_after: None }
+        } else if mentions(&message, AUTH_HINTS) {
+            ApiError::Auth(message)
+        } else {
+            ApiError::Provider(message)
+        }
+    }
+
+    /// Whether sending the same request again may succeed. Unclassified
+    /// provider errors mostly come from overloaded or failing upstreams.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ApiError::RateLimited { .. } | ApiError::ServerError { .. } | ApiError::Network(_) | ApiError::Provider(_)
+        )
+    }
+
+    /// How long the provider asked us to wait, from `Retry-After`.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ApiError::RateLimited { retry_after, .. } | ApiError::ServerError { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+/// Parses a `Retry-After` header: either delay seconds or an HTTP date.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<f64>() {
+        return (seconds >= 0.0).then(|| Duration::from_secs_f64(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = date.signed_duration_since(chrono::Utc::now());
+    Some(delay.to_std().unwrap_or(Duration::ZERO))
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            ApiError::Parse(e.to_string())
+        } else {
+            ApiError::Network(e.to_string())
+        }
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(e: serde_json::Error) -> Self {
+        ApiError::Parse(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_http_errors() {
+        assert!(matches!(ApiError::from_status(401, "Authentication Fails".into(), None), ApiError::Auth(_)));
+        assert!(matches!(ApiError::from_status(402, "Insufficient Balance".into(), None), ApiError::Auth(_)));
+        assert_eq!(
+            ApiError::from_status(429, "slow down".into(), Some(Duration::from_secs(3))).retry_after(),
+            Some(Duration::from_secs(3))
+        );
+        assert!(matches!(ApiError::from_status(503, "overloaded".into(), None), ApiError::ServerError { status: 503, .. }));
+        assert!(matches!(
+            ApiError::from_status(400, "This model's maximum context length is 65536 tokens".into(), None),
+            ApiError::ContextLengthExceeded(_)
+        ));
+        assert!(matches!(
+            ApiError::from_status(400, "Input data may contain inappropriate content. (code data_inspection_failed)".into(), None),
+            ApiError::ContentFiltered(_)
+        ));
+        assert!(matches!(ApiError::from_status(400, "Model Not Exist".into(), None), ApiError::InvalidRequest { .. }));
+    }
+
+    #[test]
+    fn classifies_embedded_errors() {
+        assert!(matches!(
+            ApiError::from_message("Output data may contain sensitive content".into()),
+            ApiError::ContentFiltered(_)
+        ));
+        assert!(matches!(ApiError::from_message("输入内容包含敏感词".into()), ApiError::ContentFiltered(_)));
+        assert!(matches!(ApiError::from_message("tool names are case sensitive".into()), ApiError::Provider(_)));
+        assert!(matches!(ApiError::from_message("参数名大小写敏感".into()), ApiError::Provider(_)));
+        assert_eq!(ApiError::from_message("overloaded".into()), ApiError::Provider("overloaded".into()));
+        assert!(matches!(
+            ApiError::from_status(400, "field names are case sensitive".into(), None),
+            ApiError::InvalidRequest { .. }
+        ));
+    }
+
+    #[test]
+    fn only_transient_errors_are_retryable() {
+        assert!(ApiError::Network("reset".into()).is_retryable());
+        assert!(ApiError::from_message("rate limit exceeded".into()).is_retryable());
+        assert!(!ApiError::Auth("bad key".into()).is_retryable());
+        assert!(!ApiError::ContextLengthExceeded("too long".into()).is_retryable());
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        assert_eq!(parse_retry_after("7"), Some(Duration::from_secs(7)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
@@ -1,4 +1,17 @@
-pub mod deepseek;
+pub mod backend;
+pub mod error;
+pub mod models;
+pub mod provider;
+pub mod retry;
+pub mod sse;
+pub mod tool_calls;
+pub mod types;
 pub mod unified;
+#[cfg(test)]
+pub mod scripted;
 
-pub use unified::{UnifiedLLMClient, ModelConfig, ModelProvider};
+pub use backend::LlmBackend;
+pub use error::ApiError;
+pub use provider::{CustomEndpoint, ModelConfig, ModelProvider};
+pub use retry::RetryPolicy;
+pub use unified::UnifiedLLMClient;
@@ -0,0 +1,166 @@
+// Built-in model catalog with context window and pricing metadata
+use super::provider::ModelProvider;
+use super::types::Usage;
+use serde::Serialize;
+
+pub struct CatalogEntry {
+    pub id: &'static str,
+    pub context_window: u32,
+    /// List price in CNY per million input tokens (cache miss)
+    pub input_price: f64,
+    /// List price in CNY per million input tokens served from the provider's cache
+    pub cached_price: f64,
+    /// List price in CNY per million output tokens
+    pub output_price: f64,
+}
+
+const fn entry(id: &'static str, context_window: u32, input_price: f64, cached_price: f64, output_price: f64) -> CatalogEntry {
+    CatalogEntry { id, context_window, input_price, cached_price, output_price }
+}
+
+// The first model of each provider is its default
+const DEEPSEEK: &[CatalogEntry] = &[
+    entry("deepseek-chat", 128_000, 2.0, 0.5, 3.0),
+    entry("deepseek-reasoner", 128_000, 2.0, 0.5, 3.0),
+];
+
+const QWEN: &[CatalogEntry] = &[
+    entry("qwen-max", 32_768, 2.4, 0.96, 9.6),
+    entry("qwen-plus", 131_072, 0.8, 0.32, 2.0),
+    entry("qwen-turbo", 1_000_000, 0.3, 0.12, 0.6),
+    entry("qwen-coder-plus", 131_072, 3.5, 1.4, 7.0),
+];
+
+const MOONSHOT: &[CatalogEntry] = &[
+    entry("kimi-k2-0905-preview", 262_144, 4.0, 1.0, 16.0),
+    entry("moonshot-v1-8k", 8_192, 2.0, 2.0, 10.0),
+    entry("moonshot-v1-32k", 32_768, 5.0, 5.0, 20.0),
+    entry("moonshot-v1-128k", 131_072, 10.0, 10.0, 30.0),
+];
+
+const ZHIPU: &[CatalogEntry] = &[
+    entry("glm-4.5", 131_072, 2.0, 0.4, 8.0),
+    entry("glm-4.5-air", 131_072, 0.8, 0.16, 2.0),
+    entry("glm-4-flash", 131_072, 0.0, 0.0, 0.0),
+];
+
+const DOUBAO: &[CatalogEntry] = &[
+    entry("doubao-seed-1-6-250615", 262_144, 0.8, 0.16, 8.0),
+    entry("doubao-1-5-pro-32k-250115", 32_768, 0.8, 0.16, 2.0),
+];
+
+const BAICHUAN: &[CatalogEntry] = &[
+    entry("Baichuan4-Turbo", 32_768, 15.0, 15.0, 15.0),
+    entry("Baichuan4-Air", 32_768, 0.98, 0.98, 0.98),
+];
+
+const MINIMAX: &[CatalogEntry] = &[
+    entry("MiniMax-M1", 1_000_000, 0.8, 0.8, 8.0),
+    entry("MiniMax-Text-01", 1_000_000, 1.0, 1.0, 8.0),
+];
+
+/// Known models of a provider; empty for custom endpoints.
+pub fn catalog(provider: &ModelProvider) -> &'static [CatalogEntry] {
+    match provider {
+        ModelProvider::DeepSeek => DEEPSEEK,
+        ModelProvider::Qwen => QWEN,
+        ModelProvider::Moonshot => MOONSHOT,
+        ModelProvider::Zhipu => ZHIPU,
+        ModelProvider::Doubao => DOUBAO,
+        ModelProvider::Baichuan => BAICHUAN,
+        ModelProvider::MiniMax => MINIMAX,
+        ModelProvider::Custom => &[],
+    }
+}
+
+pub fn lookup(provider: &ModelProvider, model: &str) -> Option<&'static CatalogEntry> {
+    catalog(provider).iter().find(|e| e.id == model)
+}
+
+/// Cost of a request in CNY, if the model's prices are known.
+pub fn cost(provider: &ModelProvider, model: &str, usage: &Usage) -> Option<f64> {
+    let prices = lookup(provider, model)?;
+    let cached = usage.cached_tokens.min(usage.prompt_tokens);
+    let uncached = usage.prompt_tokens - cached;
+    let total = uncached as f64 * prices.input_price
+        + cached as f64 * prices.cached_price
+        + usage.completion_tokens as f64 * prices.output_price;
+    Some(total / 1_000_000.0)
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct ModelInfo {
+    pub id: String,
+    pub context_window: Option<u32>,
+    pub input_price: Option<f64>,
+    pub output_price: Option<f64>,
+}
+
+impl ModelInfo {
+    fn new(provider: &ModelProvider, id: &str) -> Self {
+        let known = lookup(provider, id);
+        Self {
+            id: id.to_string(),
+            context_window: known.map(|e| e.context_window),
+            input_price: known.map(|e| e.input_price),
+            output_price: known.map(|e| e.output_price),
+        }
+    }
+}
+
+/// The built-in catalog as `ModelInfo`s.
+pub fn catalog_models(provider: &ModelProvider) -> Vec<ModelInfo> {
+    catalog(provider).iter().map(|e| ModelInfo::new(provider, e.id)).collect()
+}
+
+/// Models reported by the provider's `/models` endpoint, with catalog metadata
+/// where the model is known. Catalog models come first so defaults stay on top.
+pub fn merge_with_catalog(provider: &ModelProvider, remote_ids: &[String]) -> Vec<ModelInfo> {
+    let mut models: Vec<ModelInfo> = catalog(provider)
+        .iter()
+        .filter(|e| remote_ids.iter().any(|id| id == e.id))
+        .map(|e| ModelInfo::new(provider, e.id))
+        .collect();
+    for id in remote_ids {
+        if !models.iter().any(|m| &m.id == id) {
+            models.push(ModelInfo::new(provider, id));
+        }
+    }
+    models
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_builtin_provider_has_a_default_model() {
+        for provider in ModelProvider::BUILTIN {
+            assert!(!catalog(&provider).is_empty(), "{:?}", provider);
+        }
+    }
+
+    #[test]
+    fn prices_cached_tokens_separately() {
+        let usage = Usage { prompt_tokens: 1_000_000, completion_tokens: 500_000, cached_tokens: 400_000 };
+        // 0.6M misses at ¥2, 0.4M hits at ¥0.5, 0.5M output at ¥3
+        let cost = cost(&ModelProvider::DeepSeek, "deepseek-chat", &usage).unwrap();
+        assert!((cost - 2.9).abs() < 1e-9);
+        assert!(super::cost(&ModelProvider::Custom, "llama3", &usage).is_none());
+    }
+
+    #[test]
+    fn merges_remote_models_with_catalog() {
+        let remote = vec!["qwen-long".to_string(), "qwen-plus".to_string()];
+        let models = merge_with_catalog(&ModelProvider::Qwen, &remote);
+
+        assert_eq!(models[0].id, "qwen-plus");
+        assert_eq!(models[0].context_window, Some(131_072));
+        assert_eq!(models[1], ModelInfo {
+            id: "qwen-long".to_string(),
+            context_window: None,
+            input_price: None,
+            output_price: None,
+        });
+    }
+}
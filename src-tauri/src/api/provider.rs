@@ -0,0 +1,544 @@
+// Provider configuration and the per-provider adapters on top of the OpenAI-compatible client
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use super::error::ApiError;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
+pub enum ModelProvider {
+    #[default]
+    DeepSeek,
+    Qwen,
+    Moonshot,
+    Zhipu,
+    Doubao,
+    Baichuan,
+    MiniMax,
+    /// User-configured OpenAI-compatible endpoint (vLLM, Ollama, internal gateways)
+    Custom,
+}
+
+impl ModelProvider {
+    /// Providers with a fixed endpoint, in the order the settings list them.
+    pub const BUILTIN: [ModelProvider; 7] = [
+        ModelProvider::DeepSeek,
+        ModelProvider::Qwen,
+        ModelProvider::Moonshot,
+        ModelProvider::Zhipu,
+        ModelProvider::Doubao,
+        ModelProvider::Baichuan,
+        ModelProvider::MiniMax,
+    ];
+
+    /// Identifier used by the frontend and persisted in the keyring.
+    pub fn id(&self) -> &'static str {
+        match self {
+            ModelProvider::DeepSeek => "deepseek",
+            ModelProvider::Qwen => "qwen",
+            ModelProvider::Moonshot => "moonshot",
+            ModelProvider::Zhipu => "zhipu",
+            ModelProvider::Doubao => "doubao",
+            ModelProvider::Baichuan => "baichuan",
+            ModelProvider::MiniMax => "minimax",
+            ModelProvider::Custom => "custom",
+        }
+    }
+
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            ModelProvider::DeepSeek => "DeepSeek",
+            ModelProvider::Qwen => "Qwen",
+            ModelProvider::Moonshot => "Moonshot",
+            ModelProvider::Zhipu => "Zhipu GLM",
+            ModelProvider::Doubao => "Doubao",
+            ModelProvider::Baichuan => "Baichuan",
+            ModelProvider::MiniMax => "MiniMax",
+            ModelProvider::Custom => "Custom",
+        }
+    }
+
+    /// Unknown ids fall back to DeepSeek, the original default.
+    pub fn from_id(id: &str) -> Self {
+        if id == ModelProvider::Custom.id() {
+            return ModelProvider::Custom;
+        }
+        Self::BUILTIN
+            .into_iter()
+            .find(|p| p.id() == id)
+            .unwrap_or_default()
+    }
+
+    /// Keyring entry holding the provider's API key.
+    pub fn key_name(&self) -> &'static str {
+        match self {
+            ModelProvider::DeepSeek => "deepseek-api-key",
+            ModelProvider::Qwen => "qwen-api-key",
+            ModelProvider::Moonshot => "moonshot-api-key",
+            ModelProvider::Zhipu => "zhipu-api-key",
+            ModelProvider::Doubao => "doubao-api-key",
+            ModelProvider::Baichuan => "baichuan-api-key",
+            ModelProvider::MiniMax => "minimax-api-key",
+            ModelProvider::Custom => "custom-api-key",
+        }
+    }
+
+    pub fn adapter(&self) -> Arc<dyn ProviderAdapter> {
+        match self {
+            ModelProvider::DeepSeek => Arc::new(DeepSeekAdapter),
+            ModelProvider::Zhipu => Arc::new(ZhipuAdapter),
+            ModelProvider::Baichuan => Arc::new(BaichuanAdapter),
+            ModelProvider::MiniMax => Arc::new(MiniMaxAdapter),
+            ModelProvider::Custom => Arc::new(CustomAdapter),
+            // Fully OpenAI-compatible
+            _ => Arc::new(OpenAiAdapter),
+        }
+    }
+}
+
+/// Connection details of a custom endpoint. The API key is stored separately.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct CustomEndpoint {
+    pub base_url: String,
+    pub model_name: String,
+    #[serde(default)]
+    pub extra_headers: HashMap<String, String>,
+    /// Ask for token usage when streaming; many self-hosted servers reject
+    /// the `stream_options` field, so it is off unless the user enables it
+    #[serde(default)]
+    pub stream_usage: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct ModelConfig {
+    pub provider: ModelProvider,
+    pub model_name: String,
+    /// Empty for endpoints that need no authentication
+    pub api_key: String,
+    pub base_url: String,
+    pub extra_headers: HashMap<String, String>,
+    /// Only read by the custom endpoint adapter; see `CustomEndpoint`
+    pub stream_usage: bool,
+}
+
+impl ModelConfig {
+    fn builtin(provider: ModelProvider, model_name: &str, base_url: &str, api_key: String) -> Self {
+        Self {
+            provider,
+            model_name: model_name.to_string(),
+            api_key,
+            base_url: base_url.to_string(),
+            extra_headers: HashMap::new(),
+            stream_usage: true,
+        }
+    }
+
+    pub fn deepseek(api_key: String) -> Self {
+        Self::builtin(ModelProvider::DeepSeek, "deepseek-chat", "https://api.deepseek.com/v1", api_key)
+    }
+
+    pub fn qwen(api_key: String) -> Self {
+        // Qwen uses DashScope API
+        Self::builtin(ModelProvider::Qwen, "qwen-max", "https://dashscope.aliyuncs.com/compatible-mode/v1", api_key)
+    }
+
+    pub fn moonshot(api_key: String) -> Self {
+        Self::builtin(ModelProvider::Moonshot, "kimi-k2-0905-preview", "https://api.moonshot.cn/v1", api_key)
+    }
+
+    pub fn zhipu(api_key: String) -> Self {
+        Self::builtin(ModelProvider::Zhipu, "glm-4.5", "https://open.bigmodel.cn/api/paas/v4", api_key)
+    }
+
+    pub fn doubao(api_key: String) -> Self {
+        // Volcengine Ark; the model may also be an inference endpoint id (ep-...)
+        Self::builtin(ModelProvider::Doubao, "doubao-seed-1-6-250615", "https://ark.cn-beijing.volces.com/api/v3", api_key)
+    }
+
+    pub fn baichuan(api_key: String) -> Self {
+        Self::builtin(ModelProvider::Baichuan, "Baichuan4-Turbo", "https://api.baichuan-ai.com/v1", api_key)
+    }
+
+    pub fn minimax(api_key: String) -> Self {
+        Self::builtin(ModelProvider::MiniMax, "MiniMax-M1", "https://api.minimaxi.com/v1", api_key)
+    }
+
+    pub fn custom(endpoint: CustomEndpoint, api_key: Option<String>) -> Result<Self, String> {
+        let base_url = endpoint.base_url.trim().trim_end_matches('/');
+        if !base_url.starts_with("http://") && !base_url.starts_with("https://") {
+            return Err(format!("Invalid base URL (expected http:// or https://): {}", endpoint.base_url));
+        }
+        let model_name = endpoint.model_name.trim();
+        if model_name.is_empty() {
+            return Err("Model name is required for a custom endpoint".to_string());
+        }
+        for (name, value) in &endpoint.extra_headers {
+            reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("Invalid header name: {}", name))?;
+            reqwest::header::HeaderValue::from_str(value)
+                .map_err(|_| format!("Invalid value for header {}", name))?;
+        }
+
+        Ok(Self {
+            provider: ModelProvider::Custom,
+            model_name: model_name.to_string(),
+            api_key: api_key.unwrap_or_default(),
+            base_url: base_url.to_string(),
+            extra_headers: endpoint.extra_headers,
+            stream_usage: endpoint.stream_usage,
+        })
+    }
+
+    /// Uses `model` instead of the provider's default, if given.
+    pub fn with_model(mut self, model: Option<String>) -> Self {
+        if let Some(model) = model.filter(|m| !m.trim().is_empty()) {
+            self.model_name = model.trim().to_string();
+        }
+        self
+    }
+
+    /// Config for a built-in provider; custom endpoints go through `custom`.
+    pub fn for_provider(provider: &ModelProvider, api_key: String) -> Result<Self, String> {
+        match provider {
+            ModelProvider::DeepSeek => Ok(Self::deepseek(api_key)),
+            ModelProvider::Qwen => Ok(Self::qwen(api_key)),
+            ModelProvider::Moonshot => Ok(Self::moonshot(api_key)),
+            ModelProvider::Zhipu => Ok(Self::zhipu(api_key)),
+            ModelProvider::Doubao => Ok(Self::doubao(api_key)),
+            ModelProvider::Baichuan => Ok(Self::baichuan(api_key)),
+            ModelProvider::MiniMax => Ok(Self::minimax(api_key)),
+            ModelProvider::Custom => Err("Custom endpoint is not configured".to_string()),
+        }
+    }
+}
+
+/// Where a provider deviates from the OpenAI chat completions API.
+/// The defaults describe a fully compatible provider.
+pub trait ProviderAdapter: Send + Sync {
+    /// Headers sent in addition to `Authorization` and `Content-Type`.
+    fn extra_headers(&self, _config: &ModelConfig) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    /// Rewrites the serialized request body before it is sent.
+    fn adapt_request(&self, _body: &mut Value) {}
+
+    /// Whether streaming requests ask for usage in the last chunk
+    /// (`stream_options.include_usage`).
+    fn stream_usage(&self, _config: &ModelConfig) -> bool {
+        true
+    }
+
+    /// Removes `reasoning_content` from history the provider must not get back.
+    /// By default reasoning is never re-sent.
+    fn strip_reasoning(&self, body: &mut Value) {
+        remove_reasoning(body, usize::MAX);
+    }
+
+    /// Rewrites a raw stream chunk before it is parsed; `None` drops it.
+    /// `stream` is shared by the chunks of one response.
+    fn adapt_chunk(&self, chunk: Value, _stream: &mut StreamState) -> Option<Value> {
+        Some(chunk)
+    }
+
+    /// Error reported inside a successful response or stream chunk.
+    fn embedded_error(&self, body: &Value) -> Option<ApiError> {
+        body.get("error").filter(|e| !e.is_null()).map(|e| ApiError::from_message(error_text(e)))
+    }
+}
+
+/// What an adapter remembers between the chunks of one streamed response.
+#[derive(Default)]
+pub struct StreamState {
+    // Tool call ids in the order they first appeared
+    tool_call_ids: Vec<String>,
+}
+
+/// Human readable message from the error payloads the providers return:
+/// `{"error": {"message": ...}}`, `{"error": "..."}` or MiniMax's `base_resp`.
+pub fn error_message(body: &str) -> String {
+    let Ok(value) = serde_json::from_str::<Value>(body) else {
+        return body.to_string();
+    };
+    if let Some(error) = value.get("error").filter(|e| !e.is_null()) {
+        return error_text(error);
+    }
+    if let Some(msg) = value["base_resp"]["status_msg"].as_str() {
+        return msg.to_string();
+    }
+    value["message"].as_str().map(str::to_string).unwrap_or_else(|| body.to_string())
+}
+
+fn error_text(error: &Value) -> String {
+    let message = error["message"].as_str().or_else(|| error.as_str()).unwrap_or_default();
+    match error.get("code").filter(|c| !c.is_null()) {
+        Some(code) => format!("{} (code {})", message, code.as_str().map(str::to_string).unwrap_or_else(|| code.to_string())),
+        None if message.is_empty() => error.to_string(),
+        None => message.to_string(),
+    }
+}
+
+/// Replaces `"content": null` with an empty string. Some providers reject
+/// null content on assistant messages that only carry tool calls.
+fn fill_null_content(body: &mut Value) {
+    if let Some(messages) = body["messages"].as_array_mut() {
+        for message in messages {
+            if message.get("content").is_some_and(Value::is_null) {
+                message["content"] = Value::String(String::new());
+            }
+        }
+    }
+}
+
+/// Drops `reasoning_content` from the messages before index `keep_from`.
+fn remove_reasoning(body: &mut Value, keep_from: usize) {
+    if let Some(messages) = body["messages"].as_array_mut() {
+        for message in messages.iter_mut().take(keep_from) {
+            if let Some(message) = message.as_object_mut() {
+                message.remove("reasoning_content");
+            }
+        }
+    }
+}
+
+/// Qwen, Moonshot and Doubao.
+pub struct OpenAiAdapter;
+
+impl ProviderAdapter for OpenAiAdapter {}
+
+/// OpenAI-compatible servers of unknown vintage.
+pub struct CustomAdapter;
+
+impl ProviderAdapter for CustomAdapter {
+    fn stream_usage(&self, config: &ModelConfig) -> bool {
+        config.stream_usage
+    }
+}
+
+pub struct DeepSeekAdapter;
+
+impl ProviderAdapter for DeepSeekAdapter {
+    // In thinking mode the reasoning of the current turn must be passed back
+    // while it is still calling tools; earlier turns' reasoning is dropped
+    fn strip_reasoning(&self, body: &mut Value) {
+        let last_user = body["messages"]
+            .as_array()
+            .and_then(|messages| messages.iter().rposition(|m| m["role"] == "user"))
+            .unwrap_or(0);
+        remove_reasoning(body, last_user);
+    }
+}
+
+pub struct ZhipuAdapter;
+
+impl ProviderAdapter for ZhipuAdapter {
+    fn adapt_request(&self, body: &mut Value) {
+        fill_null_content(body);
+    }
+
+    // GLM may omit `index`, which would merge parallel calls into one.
+    // Calls are told apart by id instead, whether they arrive in one delta
+    // or one per chunk; a delta without id continues the latest call.
+    fn adapt_chunk(&self, mut chunk: Value, stream: &mut StreamState) -> Option<Value> {
+        if let Some(choices) = chunk["choices"].as_array_mut() {
+            for choice in choices {
+                if let Some(calls) = choice["delta"]["tool_calls"].as_array_mut() {
+                    for call in calls.iter_mut().filter(|call| call["index"].is_null()) {
+                        let ids = &mut stream.tool_call_ids;
+                        let index = match call["id"].as_str().filter(|id| !id.is_empty()) {
+                            Some(id) => ids.iter().position(|seen| seen == id).unwrap_or_else(|| {
+                                ids.push(id.to_string());
+                                ids.len() - 1
+                            }),
+                            None => ids.len().saturating_sub(1),
+                        };
+                        call["index"] = Value::from(index);
+                    }
+                }
+            }
+        }
+        Some(chunk)
+    }
+}
+
+pub struct BaichuanAdapter;
+
+impl ProviderAdapter for BaichuanAdapter {
+    fn adapt_request(&self, body: &mut Value) {
+        fill_null_content(body);
+    }
+}
+
+pub struct MiniMaxAdapter;
+
+impl ProviderAdapter for MiniMaxAdapter {
+    fn adapt_request(&self, body: &mut Value) {
+        fill_null_content(body);
+    }
+
+    // The stream ends with a chunk repeating the whole message under
+    // `message` instead of `delta`; replaying it would duplicate the answer.
+    // Only the usage it carries is kept.
+    fn adapt_chunk(&self, mut chunk: Value, _stream: &mut StreamState) -> Option<Value> {
+        let is_summary = chunk["choices"]
+            .as_array()
+            .is_some_and(|choices| choices.iter().any(|c| c.get("delta").is_none()));
+        if !is_summary {
+            return Some(chunk);
+        }
+        if chunk.get("usage").is_some_and(|u| !u.is_null()) {
+            chunk["choices"] = Value::Array(Vec::new());
+            return Some(chunk);
+        }
+        None
+    }
+
+    // Errors arrive with HTTP 200 and a non-zero `base_resp.status_code`
+    fn embedded_error(&self, body: &Value) -> Option<ApiError> {
+        let code = body["base_resp"]["status_code"].as_i64().unwrap_or(0);
+        if code != 0 {
+            let msg = body["base_resp"]["status_msg"].as_str().unwrap_or("unknown error");
+            let message = format!("{} (code {})", msg, code);
+            return Some(match code {
+                1002 => ApiError::RateLimited { message, retry_after: None },
+                1004 | 1008 => ApiError::Auth(message),
+                1026 | 1027 => ApiError::ContentFiltered(message),
+                1039 => ApiError::ContextLengthExceeded(message),
+                _ => ApiError::from_message(message),
+            });
+        }
+        body.get("error").filter(|e| !e.is_null()).map(|e| ApiError::from_message(error_text(e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(base_url: &str, model_name: &str) -> CustomEndpoint {
+        CustomEndpoint {
+            base_url: base_url.to_string(),
+            model_name: model_name.to_string(),
+            extra_headers: HashMap::new(),
+            stream_usage: false,
+        }
+    }
+
+    #[test]
+    fn builds_custom_config() {
+        let mut ep = endpoint(" http://10.0.0.5:8000/v1/ ", "qwen2.5-coder");
+        ep.extra_headers.insert("X-Team".to_string(), "infra".to_string());
+
+        let config = ModelConfig::custom(ep, None).unwrap();
+        assert_eq!(config.provider, ModelProvider::Custom);
+        assert_eq!(config.base_url, "http://10.0.0.5:8000/v1");
+        assert_eq!(config.api_key, "");
+        assert_eq!(config.extra_headers["X-Team"], "infra");
+    }
+
+    #[test]
+    fn custom_endpoints_ask_for_stream_usage_only_when_enabled() {
+        let config = ModelConfig::custom(endpoint("http://localhost:11434/v1", "llama3"), None).unwrap();
+        assert!(!config.provider.adapter().stream_usage(&config));
+
+        let mut ep = endpoint("http://localhost:11434/v1", "llama3");
+        ep.stream_usage = true;
+        let config = ModelConfig::custom(ep, None).unwrap();
+        assert!(config.provider.adapter().stream_usage(&config));
+
+        let config = ModelConfig::deepseek(String::new());
+        assert!(config.provider.adapter().stream_usage(&config));
+    }
+
+    #[test]
+    fn provider_ids_round_trip() {
+        for provider in ModelProvider::BUILTIN.iter().chain([&ModelProvider::Custom]) {
+            assert_eq!(&ModelProvider::from_id(provider.id()), provider);
+        }
+        assert_eq!(ModelProvider::from_id("unknown"), ModelProvider::DeepSeek);
+    }
+
+    #[test]
+    fn default_model_comes_first_in_catalog() {
+        for provider in ModelProvider::BUILTIN {
+            let config = ModelConfig::for_provider(&provider, String::new()).unwrap();
+            assert_eq!(config.model_name, crate::api::models::catalog(&provider)[0].id);
+        }
+        let config = ModelConfig::qwen(String::new()).with_model(Some("qwen-coder-plus".to_string()));
+        assert_eq!(config.model_name, "qwen-coder-plus");
+    }
+
+    #[test]
+    fn parses_provider_error_payloads() {
+        assert_eq!(error_message(r#"{"error":{"message":"Invalid API key","code":"1002"}}"#), "Invalid API key (code 1002)");
+        assert_eq!(error_message(r#"{"base_resp":{"status_code":1004,"status_msg":"login fail"}}"#), "login fail");
+        assert_eq!(error_message("Bad Gateway"), "Bad Gateway");
+
+        let body = serde_json::json!({"base_resp": {"status_code": 1008, "status_msg": "insufficient balance"}});
+        assert_eq!(MiniMaxAdapter.embedded_error(&body), Some(ApiError::Auth("insufficient balance (code 1008)".to_string())));
+        assert!(OpenAiAdapter.embedded_error(&serde_json::json!({"choices": []})).is_none());
+    }
+
+    #[test]
+    fn adapts_provider_quirks() {
+        let mut body = serde_json::json!({"messages": [{"role": "assistant", "content": null, "tool_calls": []}]});
+        BaichuanAdapter.adapt_request(&mut body);
+        assert_eq!(body["messages"][0]["content"], "");
+
+        let chunk = serde_json::json!({"choices": [{"delta": {"tool_calls": [
+            {"id": "a", "function": {"name": "read_file", "arguments": "{}"}},
+            {"id": "b", "function": {"name": "glob", "arguments": "{}"}}
+        ]}}]});
+        let chunk = ZhipuAdapter.adapt_chunk(chunk, &mut StreamState::default()).unwrap();
+        assert_eq!(chunk["choices"][0]["delta"]["tool_calls"][1]["index"], 1);
+
+        // One call per chunk, each without index
+        let mut stream = StreamState::default();
+        let indexes: Vec<_> = [Some("a"), None, Some("b"), None]
+            .into_iter()
+            .map(|id| {
+                let chunk = serde_json::json!({"choices": [{"delta": {"tool_calls": [
+                    {"id": id, "function": {"arguments": "{}"}}
+                ]}}]});
+                ZhipuAdapter.adapt_chunk(chunk, &mut stream).unwrap()["choices"][0]["delta"]["tool_calls"][0]["index"].clone()
+            })
+            .collect();
+        assert_eq!(indexes, [0, 0, 1, 1]);
+
+        let summary = serde_json::json!({"choices": [{"index": 0, "message": {"content": "hi"}}]});
+        assert!(MiniMaxAdapter.adapt_chunk(summary, &mut StreamState::default()).is_none());
+        let summary = serde_json::json!({"choices": [{"index": 0, "message": {"content": "hi"}}], "usage": {"prompt_tokens": 5}});
+        let chunk = MiniMaxAdapter.adapt_chunk(summary, &mut StreamState::default()).unwrap();
+        assert_eq!(chunk["choices"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn strips_reasoning_from_history() {
+        let body = serde_json::json!({"messages": [
+            {"role": "user", "content": "a"},
+            {"role": "assistant", "content": "b", "reasoning_content": "old"},
+            {"role": "user", "content": "c"},
+            {"role": "assistant", "content": "", "reasoning_content": "new", "tool_calls": []},
+            {"role": "tool", "content": "d"}
+        ]});
+
+        let mut deepseek = body.clone();
+        DeepSeekAdapter.strip_reasoning(&mut deepseek);
+        assert!(deepseek["messages"][1].get("reasoning_content").is_none());
+        assert_eq!(deepseek["messages"][3]["reasoning_content"], "new");
+
+        let mut other = body;
+        OpenAiAdapter.strip_reasoning(&mut other);
+        assert!(other["messages"].as_array().unwrap().iter().all(|m| m.get("reasoning_content").is_none()));
+    }
+
+    #[test]
+    fn rejects_invalid_custom_endpoints() {
+        assert!(ModelConfig::custom(endpoint("localhost:11434", "llama3"), None).is_err());
+        assert!(ModelConfig::custom(endpoint("http://localhost:11434/v1", " "), None).is_err());
+
+        let mut ep = endpoint("http://localhost:11434/v1", "llama3");
+        ep.extra_headers.insert("Bad Header".to_string(), "x".to_string());
+        assert!(ModelConfig::custom(ep, None).is_err());
+    }
+}
@@ -0,0 +1,140 @@
+// Retry policy for LLM requests: exponential backoff with jitter
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use super::error::ApiError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Total attempts, including the first one
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    /// No retry is started once this much time has passed since the first attempt
+    pub max_elapsed: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_delay: Duration::from_millis(1000),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            max_elapsed: Duration::from_secs(120),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Starts tracking the retries of one request.
+    pub fn start(&self) -> Backoff {
+        Backoff {
+            policy: *self,
+            attempts: 1,
+            started: Instant::now(),
+        }
+    }
+
+    /// Upper bound of the delay before retry number `retry` (1-based).
+    fn ceiling(&self, retry: u32) -> Duration {
+        let factor = self.multiplier.powi(retry.saturating_sub(1) as i32);
+        self.initial_delay.mul_f64(factor).min(self.max_delay)
+    }
+}
+
+/// Retry state of a single request.
+#[derive(Debug)]
+pub struct Backoff {
+    policy: RetryPolicy,
+    attempts: u32,
+    started: Instant,
+}
+
+impl Backoff {
+    /// Delay before the next attempt after `error`, or `None` to give up.
+    /// A `Retry-After` from the provider replaces the computed delay.
+    pub fn next_delay(&mut self, error: &ApiError) -> Option<Duration> {
+        if !error.is_retryable() || self.attempts >= self.policy.max_attempts {
+            return None;
+        }
+        let delay = match error.retry_after() {
+            Some(delay) => delay,
+            // Equal jitter: half fixed, half random, so parallel clients spread out
+            None => {
+                let ceiling = self.policy.ceiling(self.attempts);
+                ceiling / 2 + (ceiling / 2).mul_f64(random_fraction())
+            }
+        };
+        if self.started.elapsed() + delay > self.policy.max_elapsed {
+            return None;
+        }
+        self.attempts += 1;
+        Some(delay)
+    }
+
+    /// Attempts made so far, including the first one.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.policy.max_attempts
+    }
+}
+
+// Each RandomState gets fresh keys, which is random enough for jitter
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overloaded() -> ApiError {
+        ApiError::from_status(503, "overloaded".into(), None)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backs_off_exponentially_within_bounds() {
+        let policy = RetryPolicy {
+            max_attempts: 4,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(300),
+            multiplier: 2.0,
+            max_elapsed: Duration::from_secs(60),
+        };
+        let mut backoff = policy.start();
+
+        let delays: Vec<_> = (0..4).map(|_| backoff.next_delay(&overloaded())).collect();
+
+        assert!(matches!(delays[0], Some(d) if d >= Duration::from_millis(50) && d <= Duration::from_millis(100)));
+        assert!(matches!(delays[1], Some(d) if d >= Duration::from_millis(100) && d <= Duration::from_millis(200)));
+        assert!(matches!(delays[2], Some(d) if d >= Duration::from_millis(150) && d <= Duration::from_millis(300)));
+        assert_eq!(delays[3], None);
+        assert_eq!(backoff.attempts(), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn honors_retry_after_but_not_past_max_elapsed() {
+        let mut backoff = RetryPolicy::default().start();
+        let limited = ApiError::from_status(429, "slow down".into(), Some(Duration::from_secs(7)));
+        assert_eq!(backoff.next_delay(&limited), Some(Duration::from_secs(7)));
+
+        let too_late = ApiError::from_status(503, "overloaded".into(), Some(Duration::from_secs(600)));
+        assert_eq!(backoff.next_delay(&too_late), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn does_not_retry_auth_errors() {
+        let mut backoff = RetryPolicy::default().start();
+        assert_eq!(backoff.next_delay(&ApiError::from_status(401, "invalid api key".into(), None)), None);
+        assert_eq!(backoff.attempts(), 1);
+    }
+}
@@ -0,0 +1,206 @@
+// Scripted LLM backend that replays canned responses, for offline agent tests
+use futures::StreamExt;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use super::backend::{BackendFuture, ChunkStream, LlmBackend};
+use super::error::ApiError;
+use super::provider::ModelProvider;
+use super::unified::{ChatResponse, Message, StreamChunk, Tool};
+
+/// One scripted reply, consumed by a single `chat_completion(_stream)` call.
+pub enum ScriptedTurn {
+    /// The request itself fails (e.g. network error), before any chunk is streamed.
+    Fail(ApiError),
+    /// The request succeeds and streams these items; an `Err` item is a mid-stream failure.
+    Chunks(Vec<Result<Value, ApiError>>),
+    /// Streams these chunks, then stalls without ending the stream.
+    Stall(Vec<Value>),
+}
+
+#[derive(Default)]
+pub struct ScriptedBackend {
+    turns: Mutex<VecDeque<ScriptedTurn>>,
+    requests: Mutex<Vec<Vec<Message>>>,
+    model: Option<(ModelProvider, String)>,
+}
+
+impl ScriptedBackend {
+    pub fn new(turns: Vec<ScriptedTurn>) -> Self {
+        Self {
+            turns: Mutex::new(turns.into()),
+            requests: Mutex::new(Vec::new()),
+            model: None,
+        }
+    }
+
+    /// Reports `model` of `provider` as the one answering.
+    pub fn with_model(mut self, provider: ModelProvider, model: &str) -> Self {
+        self.model = Some((provider, model.to_string()));
+        self
+    }
+
+    /// Message lists received so far, one entry per call.
+    pub fn requests(&self) -> Vec<Vec<Message>> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.turns.lock().unwrap().len()
+    }
+
+    fn next_turn(&self, messages: Vec<Message>) -> Result<ScriptedTurn, ApiError> {
+        self.requests.lock().unwrap().push(messages);
+        self.turns
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or_else(|| ApiError::Parse("Script exhausted".to_string()))
+    }
+
+    /// A turn that streams `text` and finishes with `stop`.
+    pub fn text(text: &str) -> ScriptedTurn {
+        ScriptedTurn::Chunks(vec![Ok(content_chunk(text)), Ok(finish_chunk("stop"))])
+    }
+}
+
+/// Stream chunk carrying a content delta.
+pub fn content_chunk(text: &str) -> Value {
+    json!({
+        "id": "scripted",
+        "choices": [{ "index": 0, "delta": { "content": text }, "finish_reason": null }]
+    })
+}
+
+/// Stream chunk carrying a reasoning delta, as sent by thinking models.
+pub fn reasoning_chunk(text: &str) -> Value {
+    json!({
+        "id": "scripted",
+        "choices": [{ "index": 0, "delta": { "reasoning_content": text }, "finish_reason": null }]
+    })
+}
+
+/// Stream chunk carrying one tool-call delta. Every field except `index` is optional,
+/// mirroring how providers fragment a call across chunks.
+pub fn tool_call_chunk(index: i32, id: Option<&str>, name: Option<&str>, arguments: Option<&str>) -> Value {
+    let mut function = json!({});
+    if let Some(name) = name {
+        function["name"] = json!(name);
+    }
+    if let Some(arguments) = arguments {
+        function["arguments"] = json!(arguments);
+    }
+    let mut call = json!({ "index": index, "function": function });
+    if let Some(id) = id {
+        call["id"] = json!(id);
+        call["type"] = json!("function");
+    }
+    json!({
+        "id": "scripted",
+        "choices": [{ "index": 0, "delta": { "tool_calls": [call] }, "finish_reason": null }]
+    })
+}
+
+/// Trailing chunk sent when usage is requested: no choices, only token counts.
+pub fn usage_chunk(prompt_tokens: u32, completion_tokens: u32) -> Value {
+    json!({
+        "id": "scripted",
+        "choices": [],
+        "usage": {
+            "prompt_tokens": prompt_tokens,
+            "completion_tokens": completion_tokens,
+            "total_tokens": prompt_tokens + completion_tokens
+        }
+    })
+}
+
+/// Final stream chunk with an empty delta and the given finish reason.
+pub fn finish_chunk(reason: &str) -> Value {
+    json!({
+        "id": "scripted",
+        "choices": [{ "index": 0, "delta": {}, "finish_reason": reason }]
+    })
+}
+
+fn parse_chunk(value: Value) -> Result<StreamChunk, ApiError> {
+    serde_json::from_value(value).map_err(|e| ApiError::Parse(format!("Invalid scripted chunk: {}", e)))
+}
+
+impl LlmBackend for ScriptedBackend {
+    fn model(&self) -> Option<(&ModelProvider, &str)> {
+        self.model.as_ref().map(|(provider, model)| (provider, model.as_str()))
+    }
+
+    /// Folds the content deltas of the next turn into a single response. Tool-call
+    /// deltas are not aggregated; use the streaming path to script tool calls.
+    fn chat_completion(
+        &self,
+        messages: Vec<Message>,
+        _tools: Option<Vec<Tool>>,
+    ) -> BackendFuture<'_, ChatResponse> {
+        let turn = self.next_turn(messages);
+        Box::pin(async move {
+            let items = match turn? {
+                ScriptedTurn::Fail(e) => return Err(e),
+                ScriptedTurn::Chunks(items) => items,
+                ScriptedTurn::Stall(_) => return std::future::pending().await,
+            };
+
+            let mut content = String::new();
+            let mut finish_reason = None;
+            let mut usage = None;
+            for item in items {
+                let chunk = parse_chunk(item?)?;
+                if chunk.usage.is_some() {
+                    usage = chunk.usage;
+                }
+                if let Some(choice) = chunk.choices.into_iter().next() {
+                    if let Some(text) = choice.delta.content {
+                        content.push_str(&text);
+                    }
+                    if choice.finish_reason.is_some() {
+                        finish_reason = choice.finish_reason;
+                    }
+                }
+            }
+
+            let mut response: ChatResponse = serde_json::from_value(json!({
+                "id": "scripted",
+                "choices": [{
+                    "index": 0,
+                    "message": { "role": "assistant", "content": content },
+                    "finish_reason": finish_reason,
+                }]
+            }))?;
+            response.usage = usage;
+            Ok(response)
+        })
+    }
+
+    fn chat_completion_stream(
+        &self,
+        messages: Vec<Message>,
+        _tools: Option<Vec<Tool>>,
+    ) -> BackendFuture<'_, ChunkStream> {
+        let turn = self.next_turn(messages);
+        Box::pin(async move {
+            let items = match turn? {
+                ScriptedTurn::Fail(e) => return Err(e),
+                ScriptedTurn::Chunks(items) => items,
+                ScriptedTurn::Stall(items) => {
+                    let chunks: Vec<_> = items.into_iter().map(parse_chunk).collect();
+                    let stream: ChunkStream = Box::pin(futures::stream::iter(chunks).chain(futures::stream::pending()));
+                    return Ok(stream);
+                },
+            };
+
+            let chunks: Vec<_> = items
+                .into_iter()
+                .map(|item| item.and_then(parse_chunk))
+                .collect();
+            let stream: ChunkStream = Box::pin(futures::stream::iter(chunks));
+            Ok(stream)
+        })
+    }
+}
@@ -0,0 +1,257 @@
+// Server-Sent Events decoding for streaming chat completions
+use futures::{Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use std::fmt::Display;
+use super::error::ApiError;
+use super::provider::error_message;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub data: String,
+    pub id: Option<String>,
+}
+
+/// Incremental SSE decoder. Network chunks may end anywhere, including in the
+/// middle of a line or of a multi-byte UTF-8 character, so bytes are buffered
+/// until a full line is available.
+#[derive(Default)]
+pub struct SseDecoder {
+    buf: Vec<u8>,
+    // The previous chunk ended with `\r`, so a leading `\n` belongs to that line ending
+    skip_lf: bool,
+    started: bool,
+    event: Option<String>,
+    data: String,
+    has_data: bool,
+    // Last event id, which carries over to later events
+    id: Option<String>,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds raw bytes and returns the events completed by them.
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<SseEvent> {
+        let mut bytes = bytes;
+        if self.skip_lf && !bytes.is_empty() {
+            if bytes[0] == b'\n' {
+                bytes = &bytes[1..];
+            }
+            self.skip_lf = false;
+        }
+        self.buf.extend_from_slice(bytes);
+
+        let mut events = Vec::new();
+        let mut start = 0;
+        let mut i = 0;
+        while i < self.buf.len() {
+            match self.buf[i] {
+                b'\n' | b'\r' => {
+                    let line = self.buf[start..i].to_vec();
+                    if self.buf[i] == b'\r' {
+                        if i + 1 < self.buf.len() {
+                            if self.buf[i + 1] == b'\n' {
+                                i += 1;
+                            }
+                        } else {
+                            self.skip_lf = true;
+                        }
+                    }
+                    i += 1;
+                    start = i;
+                    if let Some(event) = self.process_line(&line) {
+                        events.push(event);
+                    }
+                }
+                _ => i += 1,
+            }
+        }
+        self.buf.drain(..start);
+        events
+    }
+
+    /// Flushes what is left when the stream ends. Some servers close the
+    /// connection without the blank line that terminates the last event.
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        if !self.buf.is_empty() {
+            let line = std::mem::take(&mut self.buf);
+            if let Some(event) = self.process_line(&line) {
+                return Some(event);
+            }
+        }
+        self.dispatch()
+    }
+
+    fn process_line(&mut self, line: &[u8]) -> Option<SseEvent> {
+        // Lines end at ASCII bytes, so a complete line never splits a UTF-8 character
+        let mut line = String::from_utf8_lossy(line).into_owned();
+        if !self.started {
+            self.started = true;
+            if let Some(stripped) = line.strip_prefix('\u{feff}') {
+                line = stripped.to_string();
+            }
+        }
+
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            return None; // Comment / keep-alive
+        }
+
+        let (field, value) = match line.find(':') {
+            Some(pos) => {
+                let value = &line[pos + 1..];
+                (&line[..pos], value.strip_prefix(' ').unwrap_or(value))
+            }
+            None => (line.as_str(), ""),
+        };
+        match field {
+            "data" => {
+                if self.has_data {
+                    self.data.push('\n');
+                }
+                self.data.push_str(value);
+                self.has_data = true;
+            }
+            "event" => self.event = Some(value.to_string()),
+            "id" if !value.contains('\0') => self.id = Some(value.to_string()),
+            _ => {} // `retry` and unknown fields are ignored
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        if !self.has_data {
+            return None;
+        }
+        self.has_data = false;
+        Some(SseEvent {
+            event,
+            data: std::mem::take(&mut self.data),
+            id: self.id.clone(),
+        })
+    }
+}
+
+/// Turns a streaming response body into the JSON payloads of its events,
+/// skipping the OpenAI-style `[DONE]` terminator.
+pub fn json_events<T, S, B, E>(body: S) -> impl Stream<Item = Result<T, ApiError>> + Send
+where
+    T: DeserializeOwned + Send + 'static,
+    S: Stream<Item = Result<B, E>> + Send + 'static,
+    B: AsRef<[u8]>,
+    E: Display,
+{
+    let mut decoder = SseDecoder::new();
+    body.map(Some)
+        .chain(futures::stream::once(async { None }))
+        .flat_map(move |item| {
+            let events = match item {
+                Some(Ok(bytes)) => decoder.feed(bytes.as_ref()),
+                Some(Err(e)) => return futures::stream::iter(vec![Err(ApiError::Network(e.to_string()))]),
+                None => decoder.finish().into_iter().collect(),
+            };
+            let results: Vec<_> = events
+                .into_iter()
+                .filter(|e| e.data != "[DONE]")
+                .map(|e| {
+                    if e.event.as_deref() == Some("error") {
+                        return Err(ApiError::from_message(error_message(&e.data)));
+                    }
+                    serde_json::from_str::<T>(&e.data).map_err(ApiError::from)
+                })
+                .collect();
+            futures::stream::iter(results)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn feed_bytewise(input: &[u8]) -> Vec<SseEvent> {
+        let mut decoder = SseDecoder::new();
+        let mut events = Vec::new();
+        for b in input {
+            events.extend(decoder.feed(std::slice::from_ref(b)));
+        }
+        events.extend(decoder.finish());
+        events
+    }
+
+    fn data(s: &str) -> SseEvent {
+        SseEvent {
+            data: s.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn decodes_byte_by_byte() {
+        let input = "data: {\"content\":\"你好🌍\"}\n\ndata: [DONE]\n\n";
+        let events = feed_bytewise(input.as_bytes());
+        assert_eq!(events, vec![data("{\"content\":\"你好🌍\"}"), data("[DONE]")]);
+    }
+
+    #[test]
+    fn handles_all_line_endings() {
+        let events = feed_bytewise(b"data: a\r\n\r\ndata: b\r\rdata: c\n\n");
+        assert_eq!(events, vec![data("a"), data("b"), data("c")]);
+    }
+
+    #[test]
+    fn joins_multi_line_data_and_reads_fields() {
+        let input = "\u{feff}: keep-alive\nevent: message\nid: 7\ndata: first\ndata:second\nretry: 1000\n\ndata: next\n\n";
+        let events = feed_bytewise(input.as_bytes());
+        assert_eq!(events, vec![
+            SseEvent {
+                event: Some("message".to_string()),
+                data: "first\nsecond".to_string(),
+                id: Some("7".to_string()),
+            },
+            SseEvent {
+                event: None,
+                data: "next".to_string(),
+                id: Some("7".to_string()),
+            },
+        ]);
+    }
+
+    #[test]
+    fn ignores_events_without_data_and_flushes_at_end() {
+        let events = feed_bytewise(b"event: ping\n\n: comment\n\ndata: tail");
+        assert_eq!(events, vec![data("tail")]);
+    }
+
+    #[tokio::test]
+    async fn parses_json_split_across_chunks() {
+        let body = "data: {\"n\":1}\n\ndata: {\"text\":\"é\"}\n\ndata: [DONE]\n\n".as_bytes();
+        // Split inside the JSON and inside the two-byte `é`
+        let split = body.iter().position(|&b| b == 0xC3).unwrap() + 1;
+        let chunks: Vec<Result<Vec<u8>, std::io::Error>> = vec![
+            Ok(body[..8].to_vec()),
+            Ok(body[8..split].to_vec()),
+            Ok(body[split..].to_vec()),
+        ];
+
+        let values: Vec<Value> = json_events(futures::stream::iter(chunks))
+            .map(|r| r.unwrap())
+            .collect()
+            .await;
+        assert_eq!(values, vec![serde_json::json!({"n": 1}), serde_json::json!({"text": "é"})]);
+    }
+
+    #[tokio::test]
+    async fn reports_error_events() {
+        let chunks: Vec<Result<&[u8], std::io::Error>> = vec![Ok(b"event: error\ndata: {\"message\":\"overloaded\"}\n\n")];
+        let results: Vec<Result<Value, _>> = json_events(futures::stream::iter(chunks)).collect().await;
+        assert_eq!(results.len(), 1);
+        assert!(matches!(results[0], Err(ApiError::Provider(ref message)) if message == "overloaded"));
+    }
+}
@@ -0,0 +1,148 @@
+// Assembles streamed tool-call deltas into complete calls
+use std::collections::{BTreeMap, HashSet};
+
+use super::error::ApiError;
+use super::types::{FunctionCall, StreamToolCall, ToolCall};
+
+#[derive(Default)]
+struct PartialCall {
+    id: Option<String>,
+    name: Option<String>,
+    arguments: String,
+}
+
+/// Tool calls of one streamed reply, keyed by their `index` so they come out
+/// in the order the model emitted them, however the deltas interleave.
+#[derive(Default)]
+pub struct ToolCallAccumulator {
+    calls: BTreeMap<i32, PartialCall>,
+}
+
+impl ToolCallAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, delta: &StreamToolCall) {
+        let call = self.calls.entry(delta.index).or_default();
+        if let Some(id) = delta.id.as_ref().filter(|id| !id.is_empty()) {
+            call.id = Some(id.clone());
+        }
+        if let Some(function) = &delta.function {
+            if let Some(name) = function.name.as_ref().filter(|name| !name.is_empty()) {
+                call.name = Some(name.clone());
+            }
+            if let Some(arguments) = &function.arguments {
+                call.arguments.push_str(arguments);
+            }
+        }
+    }
+
+    /// The complete calls in index order. A call without a name cannot be run
+    /// and fails the reply.
+    ///
+    /// A missing id is filled in rather than failing the reply: some
+    /// OpenAI-compatible servers stream calls without ids, and the id is only
+    /// used to pair each call with the tool result we send back in the next
+    /// request, where the server matches them up within our own history.
+    /// That stays sound as long as the filled-in id is unique in the reply.
+    pub fn finish(self) -> Result<Vec<ToolCall>, ApiError> {
+        let taken: HashSet<String> = self.calls.values().filter_map(|call| call.id.clone()).collect();
+        let mut next_free = 0;
+        let mut free_id = move || loop {
+            let id = format!("call_{}", next_free);
+            next_free += 1;
+            if !taken.contains(&id) {
+                return id;
+            }
+        };
+        self.calls
+            .into_iter()
+            .map(|(index, call)| {
+                let name = call
+                    .name
+                    .ok_or_else(|| ApiError::Parse(format!("Tool call {} has no function name", index)))?;
+                // Tools without parameters are sometimes called with no arguments at all
+                let arguments = if call.arguments.trim().is_empty() {
+                    "{}".to_string()
+                } else {
+                    call.arguments
+                };
+                Ok(ToolCall {
+                    id: call.id.unwrap_or_else(&mut free_id),
+                    r#type: "function".to_string(),
+                    function: FunctionCall { name, arguments },
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn delta(index: i32, id: Option<&str>, name: Option<&str>, arguments: Option<&str>) -> StreamToolCall {
+        serde_json::from_value(json!({
+            "index": index,
+            "id": id,
+            "function": { "name": name, "arguments": arguments },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn orders_interleaved_calls_by_index() {
+        let mut calls = ToolCallAccumulator::new();
+        for d in [
+            delta(2, Some("call_c"), Some("grep"), Some("{\"pattern\":")),
+            delta(0, Some("call_a"), Some("read_file"), None),
+            delta(1, Some("call_b"), Some("glob"), Some("{\"pattern\":\"*.rs\"}")),
+            delta(0, None, None, Some("{\"path\":\"a.rs\"}")),
+            delta(2, None, None, Some("\"fn main\"}")),
+        ] {
+            calls.push(&d);
+        }
+
+        let calls = calls.finish().unwrap();
+
+        let ids: Vec<_> = calls.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, ["call_a", "call_b", "call_c"]);
+        assert_eq!(calls[0].function.arguments, "{\"path\":\"a.rs\"}");
+        assert_eq!(calls[2].function.arguments, "{\"pattern\":\"fn main\"}");
+    }
+
+    #[test]
+    fn fills_missing_id_and_empty_arguments() {
+        let mut calls = ToolCallAccumulator::new();
+        calls.push(&delta(0, None, Some("project_structure"), Some("")));
+
+        let calls = calls.finish().unwrap();
+
+        assert_eq!(calls[0].id, "call_0");
+        assert_eq!(calls[0].function.arguments, "{}");
+    }
+
+    #[test]
+    fn filled_in_ids_do_not_collide_with_real_ones() {
+        let mut calls = ToolCallAccumulator::new();
+        calls.push(&delta(0, None, Some("read_file"), Some("{}")));
+        calls.push(&delta(1, Some("call_0"), Some("glob"), Some("{}")));
+        calls.push(&delta(2, None, Some("grep"), Some("{}")));
+
+        let calls = calls.finish().unwrap();
+
+        let ids: Vec<_> = calls.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, ["call_1", "call_0", "call_2"]);
+    }
+
+    #[test]
+    fn rejects_call_without_name() {
+        let mut calls = ToolCallAccumulator::new();
+        calls.push(&delta(0, Some("call_a"), Some("read_file"), Some("{}")));
+        calls.push(&delta(1, Some("call_b"), None, Some("{}")));
+
+        assert!(matches!(calls.finish(), Err(ApiError::Parse(msg)) if msg.contains("Tool call 1")));
+    }
+}
@@ -0,0 +1,287 @@
+// OpenAI-compatible chat completion wire types, shared by all providers
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Message {
+    pub role: String,
+    pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Chain of thought of reasoning models, kept apart from the answer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ToolCall {
+    pub id: String,
+    pub r#type: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunctionCall {
+    pub name: String,
+    pub arguments: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ChatRequest {
+    pub model: String,
+    pub messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    pub stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+    #[serde(flatten)]
+    pub params: GenerationParams,
+}
+
+#[derive(Serialize, Debug)]
+pub struct StreamOptions {
+    /// Ask for a final chunk carrying the token usage of the request
+    pub include_usage: bool,
+}
+
+/// Optional sampling parameters; unset ones are left to the provider's defaults.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct GenerationParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    JsonObject,
+}
+
+impl GenerationParams {
+    /// These parameters with every field set in `overrides` replaced.
+    pub fn overridden_by(&self, overrides: &GenerationParams) -> GenerationParams {
+        GenerationParams {
+            temperature: overrides.temperature.or(self.temperature),
+            top_p: overrides.top_p.or(self.top_p),
+            max_tokens: overrides.max_tokens.or(self.max_tokens),
+            stop: overrides.stop.clone().or_else(|| self.stop.clone()),
+            seed: overrides.seed.or(self.seed),
+            presence_penalty: overrides.presence_penalty.or(self.presence_penalty),
+            frequency_penalty: overrides.frequency_penalty.or(self.frequency_penalty),
+            response_format: overrides.response_format.clone().or_else(|| self.response_format.clone()),
+        }
+    }
+
+    /// Checks the ranges the OpenAI API accepts, so mistakes surface when
+    /// the parameters are saved rather than on the next request.
+    pub fn validate(&self) -> Result<(), String> {
+        let in_range = |name: &str, value: Option<f32>, min: f32, max: f32| match value {
+            Some(v) if !(min..=max).contains(&v) => Err(format!("{} must be between {} and {}", name, min, max)),
+            _ => Ok(()),
+        };
+        in_range("temperature", self.temperature, 0.0, 2.0)?;
+        in_range("top_p", self.top_p, 0.0, 1.0)?;
+        in_range("presence_penalty", self.presence_penalty, -2.0, 2.0)?;
+        in_range("frequency_penalty", self.frequency_penalty, -2.0, 2.0)?;
+        if self.max_tokens == Some(0) {
+            return Err("max_tokens must be greater than 0".to_string());
+        }
+        if let Some(stop) = &self.stop {
+            if stop.len() > 4 || stop.iter().any(String::is_empty) {
+                return Err("stop takes up to 4 non-empty sequences".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Tool {
+    pub r#type: String,
+    pub function: ToolFunction,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ToolFunction {
+    pub name: String,
+    pub description: String,
+    pub parameters: Value,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChatResponse {
+    pub id: String,
+    pub choices: Vec<Choice>,
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Choice {
+    pub index: i32,
+    pub message: Message,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StreamChunk {
+    pub id: String,
+    pub choices: Vec<StreamChoice>,
+    // Only on the last chunk, which has no choices
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+/// Token counts of one request. `prompt_tokens` includes the cached ones.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(from = "RawUsage")]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub cached_tokens: u32,
+}
+
+// Providers report cache hits under different names
+#[derive(Deserialize)]
+struct RawUsage {
+    // Some providers send null rather than omitting a count
+    prompt_tokens: Option<u32>,
+    completion_tokens: Option<u32>,
+    // DeepSeek
+    prompt_cache_hit_tokens: Option<u32>,
+    // OpenAI, Qwen, Doubao and Zhipu
+    prompt_tokens_details: Option<PromptTokensDetails>,
+    // Moonshot
+    cached_tokens: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct PromptTokensDetails {
+    cached_tokens: Option<u32>,
+}
+
+impl From<RawUsage> for Usage {
+    fn from(raw: RawUsage) -> Self {
+        let cached_tokens = raw
+            .prompt_cache_hit_tokens
+            .or(raw.prompt_tokens_details.and_then(|d| d.cached_tokens))
+            .or(raw.cached_tokens)
+            .unwrap_or(0);
+        Usage {
+            prompt_tokens: raw.prompt_tokens.unwrap_or(0),
+            completion_tokens: raw.completion_tokens.unwrap_or(0),
+            cached_tokens,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StreamChoice {
+    pub index: i32,
+    pub delta: StreamDelta,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StreamDelta {
+    pub role: Option<String>,
+    pub content: Option<String>,
+    pub reasoning_content: Option<String>,
+    pub tool_calls: Option<Vec<StreamToolCall>>,
+}
+
+// Tool call delta for streaming (has index and optional fields)
+#[derive(Deserialize, Debug)]
+pub struct StreamToolCall {
+    pub index: i32,
+    pub id: Option<String>,
+    pub r#type: Option<String>,
+    pub function: Option<StreamFunctionCall>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StreamFunctionCall {
+    pub name: Option<String>,
+    pub arguments: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sends_only_set_generation_params() {
+        let global = GenerationParams {
+            temperature: Some(0.7),
+            max_tokens: Some(4096),
+            ..Default::default()
+        };
+        let session = GenerationParams {
+            temperature: Some(0.0),
+            response_format: Some(ResponseFormat::JsonObject),
+            ..Default::default()
+        };
+        let request = ChatRequest {
+            model: "deepseek-chat".to_string(),
+            messages: Vec::new(),
+            tools: None,
+            stream: true,
+            stream_options: None,
+            params: global.overridden_by(&session),
+        };
+
+        let body = serde_json::to_value(&request).unwrap();
+        assert_eq!(body["temperature"], 0.0);
+        assert_eq!(body["max_tokens"], 4096);
+        assert_eq!(body["response_format"], serde_json::json!({"type": "json_object"}));
+        assert!(body.get("top_p").is_none());
+        assert!(body.get("stop").is_none());
+    }
+
+    #[test]
+    fn reads_cached_tokens_of_each_provider() {
+        let parse = |json: Value| serde_json::from_value::<Usage>(json).unwrap().cached_tokens;
+        assert_eq!(parse(serde_json::json!({"prompt_tokens": 10, "completion_tokens": 2, "prompt_cache_hit_tokens": 8})), 8);
+        assert_eq!(parse(serde_json::json!({"prompt_tokens": 10, "prompt_tokens_details": {"cached_tokens": 6}})), 6);
+        assert_eq!(parse(serde_json::json!({"prompt_tokens": 10, "cached_tokens": 4})), 4);
+        assert_eq!(parse(serde_json::json!({"prompt_tokens": 10, "completion_tokens": 2})), 0);
+        assert_eq!(parse(serde_json::json!({"prompt_tokens": 10, "prompt_tokens_details": {"cached_tokens": null}})), 0);
+        assert_eq!(parse(serde_json::json!({"prompt_tokens": 10, "prompt_tokens_details": {}, "cached_tokens": 4})), 4);
+        let usage: Usage = serde_json::from_value(serde_json::json!({"prompt_tokens": 10, "completion_tokens": null})).unwrap();
+        assert_eq!(usage.completion_tokens, 0);
+
+        let chunk: StreamChunk = serde_json::from_value(serde_json::json!({
+            "id": "x", "choices": [], "usage": {"prompt_tokens": 3, "completion_tokens": 1, "total_tokens": 4}
+        })).unwrap();
+        assert_eq!(chunk.usage, Some(Usage { prompt_tokens: 3, completion_tokens: 1, cached_tokens: 0 }));
+    }
+
+    #[test]
+    fn validates_generation_params() {
+        assert!(GenerationParams::default().validate().is_ok());
+        assert!(GenerationParams { temperature: Some(2.5), ..Default::default() }.validate().is_err());
+        assert!(GenerationParams { max_tokens: Some(0), ..Default::default() }.validate().is_err());
+        let stop = Some(vec!["a".to_string(); 5]);
+        assert!(GenerationParams { stop, ..Default::default() }.validate().is_err());
+    }
+}
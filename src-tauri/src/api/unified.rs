@@ -1,146 +1,117 @@
-// Unified LLM client interface supporting multiple providers
+// Unified LLM client: one OpenAI-compatible core for every provider
 use reqwest::Client;
-use serde::{Deserialize, Serialize};
-use std::error::Error;
-use futures::Stream;
-use futures::StreamExt;
+use std::sync::Arc;
+use futures::{Stream, StreamExt};
+use serde_json::Value;
 use std::pin::Pin;
+use std::time::Duration;
+use super::error::{parse_retry_after, ApiError};
+use super::provider::{error_message, ModelConfig, ModelProvider, ProviderAdapter, StreamState};
+use super::sse::json_events;
 
 // Re-export types that are used across the codebase
-pub use super::deepseek::{Message, Tool, StreamToolCall};
+pub use super::types::{ChatRequest, ChatResponse, GenerationParams, Message, StreamChunk, StreamOptions, Tool};
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
-pub enum ModelProvider {
-    DeepSeek,
-    Qwen,
-    // Can add more providers here
-}
-
-impl Default for ModelProvider {
-    fn default() -> Self {
-        ModelProvider::DeepSeek
-    }
-}
-
-#[derive(Clone, Debug)]
-pub struct ModelConfig {
-    pub provider: ModelProvider,
-    pub model_name: String,
-    pub api_key: String,
-    pub base_url: String,
-}
-
-impl ModelConfig {
-    pub fn deepseek(api_key: String) -> Self {
-        Self {
-            provider: ModelProvider::DeepSeek,
-            model_name: "deepseek-chat".to_string(),
-            api_key,
-            base_url: "https://api.deepseek.com/v1".to_string(),
-        }
-    }
-    
-    pub fn qwen(api_key: String) -> Self {
-        Self {
-            provider: ModelProvider::Qwen,
-            model_name: "qwen-max".to_string(),
-            api_key,
-            // Qwen uses DashScope API
-            base_url: "https://dashscope.aliyuncs.com/compatible-mode/v1".to_string(),
-        }
-    }
-}
-
-#[derive(Deserialize, Debug)]
-pub struct ChatResponse {
-    pub id: String,
-    pub choices: Vec<Choice>,
-}
-
-#[derive(Deserialize, Debug)]
-pub struct Choice {
-    pub index: i32,
-    pub message: Message,
-    pub finish_reason: Option<String>,
-}
-
-#[derive(Deserialize, Debug)]
-pub struct StreamChunk {
-    pub id: String,
-    pub choices: Vec<StreamChoice>,
-}
-
-#[derive(Deserialize, Debug)]
-pub struct StreamChoice {
-    pub index: i32,
-    pub delta: StreamDelta,
-    pub finish_reason: Option<String>,
-}
-
-#[derive(Deserialize, Debug)]
-pub struct StreamDelta {
-    pub role: Option<String>,
-    pub content: Option<String>,
-    pub tool_calls: Option<Vec<StreamToolCall>>,
-}
-
-#[derive(Serialize, Debug)]
-struct ChatRequest {
-    model: String,
-    messages: Vec<Message>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    tools: Option<Vec<Tool>>,
-    stream: bool,
-}
+// A stalled connection must fail so the retry policy can take over. The read
+// timeout is per read, not per response: streams deliver chunks well within
+// it, and reasoning models stream their thinking instead of going quiet
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+const READ_TIMEOUT: Duration = Duration::from_secs(180);
 
 #[derive(Clone)]
 pub struct UnifiedLLMClient {
     config: ModelConfig,
+    adapter: Arc<dyn ProviderAdapter>,
+    params: GenerationParams,
     client: Client,
 }
 
 impl UnifiedLLMClient {
     pub fn new(config: ModelConfig) -> Self {
         Self {
+            adapter: config.provider.adapter(),
             config,
-            client: Client::new(),
+            params: GenerationParams::default(),
+            client: Client::builder()
+                .connect_timeout(CONNECT_TIMEOUT)
+                .read_timeout(READ_TIMEOUT)
+                .build()
+                .expect("HTTP client configuration is valid"),
         }
     }
-    
+
+    /// Sampling parameters sent with every chat request.
+    pub fn with_params(mut self, params: GenerationParams) -> Self {
+        self.params = params;
+        self
+    }
+
     pub fn provider(&self) -> &ModelProvider {
         &self.config.provider
     }
-    
+
     pub fn model_name(&self) -> &str {
         &self.config.model_name
     }
 
+    /// Sends a chat completion request and checks the HTTP status.
+    async fn send(&self, request: ChatRequest) -> Result<reqwest::Response, ApiError> {
+        let mut body = serde_json::to_value(&request)?;
+        self.adapter.strip_reasoning(&mut body);
+        self.adapter.adapt_request(&mut body);
+
+        let builder = self.client.post(format!("{}/chat/completions", self.config.base_url));
+        let response = self.with_headers(builder).json(&body).send().await?;
+        check_status(response).await
+    }
+
+    fn with_headers(&self, mut builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        builder = builder.header("Content-Type", "application/json");
+        // Self-hosted endpoints often run without authentication
+        if !self.config.api_key.is_empty() {
+            builder = builder.header("Authorization", format!("Bearer {}", self.config.api_key));
+        }
+        for (name, value) in &self.config.extra_headers {
+            builder = builder.header(name, value);
+        }
+        for (name, value) in self.adapter.extra_headers(&self.config) {
+            builder = builder.header(name, value);
+        }
+        builder
+    }
+
+    /// Model ids from the provider's `/models` endpoint.
+    pub async fn list_models(&self) -> Result<Vec<String>, ApiError> {
+        let builder = self.client.get(format!("{}/models", self.config.base_url));
+        let body = check_status(self.with_headers(builder).send().await?).await?.json::<Value>().await?;
+        let ids = body["data"]
+            .as_array()
+            .ok_or_else(|| ApiError::Parse("Unexpected /models response".to_string()))?
+            .iter()
+            .filter_map(|m| m["id"].as_str().map(str::to_string))
+            .collect();
+        Ok(ids)
+    }
+
     pub async fn chat_completion(
         &self,
         messages: Vec<Message>,
         tools: Option<Vec<Tool>>,
-    ) -> Result<ChatResponse, Box<dyn Error + Send + Sync>> {
+    ) -> Result<ChatResponse, ApiError> {
         let request = ChatRequest {
             model: self.config.model_name.clone(),
             messages,
             tools,
             stream: false,
+            stream_options: None,
+            params: self.params.clone(),
         };
 
-        let response = self.client
-            .post(format!("{}/chat/completions", self.config.base_url))
-            .header("Authorization", format!("Bearer {}", self.config.api_key))
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            return Err(format!("API Error: {}", error_text).into());
+        let body = self.send(request).await?.json::<Value>().await?;
+        if let Some(error) = self.adapter.embedded_error(&body) {
+            return Err(error);
         }
-
-        let chat_response = response.json::<ChatResponse>().await?;
+        let chat_response = serde_json::from_value::<ChatResponse>(body)?;
         Ok(chat_response)
     }
 
@@ -148,52 +119,49 @@ impl UnifiedLLMClient {
         &self,
         messages: Vec<Message>,
         tools: Option<Vec<Tool>>,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamChunk, Box<dyn Error + Send + Sync>>> + Send>>, Box<dyn Error + Send + Sync>> {
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamChunk, ApiError>> + Send>>, ApiError> {
         let request = ChatRequest {
             model: self.config.model_name.clone(),
             messages,
             tools,
             stream: true,
+            stream_options: self
+                .adapter
+                .stream_usage(&self.config)
+                .then_some(StreamOptions { include_usage: true }),
+            params: self.params.clone(),
         };
 
-        let response = self.client
-            .post(format!("{}/chat/completions", self.config.base_url))
-            .header("Authorization", format!("Bearer {}", self.config.api_key))
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            return Err(format!("API Error: {}", error_text).into());
-        }
-
-        let stream = response.bytes_stream()
-            .map(|result| {
-                match result {
-                    Ok(bytes) => {
-                        let text = String::from_utf8_lossy(&bytes);
-                        let mut chunks = Vec::new();
-                        for line in text.lines() {
-                            if line.starts_with("data: ") {
-                                let data = &line[6..];
-                                if data == "[DONE]" {
-                                    continue;
-                                }
-                                match serde_json::from_str::<StreamChunk>(data) {
-                                    Ok(chunk) => chunks.push(Ok(chunk)),
-                                    Err(e) => chunks.push(Err(format!("JSON Parse Error: {}", e).into())),
-                                }
-                            }
-                        }
-                        futures::stream::iter(chunks)
-                    },
-                    Err(e) => futures::stream::iter(vec![Err(e.into())]),
-                }
-            })
-            .flatten();
-
+        let response = self.send(request).await?;
+        let adapter = self.adapter.clone();
+        let mut state = StreamState::default();
+        let stream = json_events::<Value, _, _, _>(response.bytes_stream())
+            .filter_map(move |event| {
+                let result = event.and_then(|chunk| {
+                    if let Some(error) = adapter.embedded_error(&chunk) {
+                        return Err(error);
+                    }
+                    match adapter.adapt_chunk(chunk, &mut state) {
+                        Some(chunk) => Ok(Some(serde_json::from_value::<StreamChunk>(chunk)?)),
+                        None => Ok(None),
+                    }
+                });
+                futures::future::ready(result.transpose())
+            });
         Ok(Box::pin(stream))
     }
 }
+
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, ApiError> {
+    let status = response.status();
+    if !status.is_success() {
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after);
+        let error_text = response.text().await?;
+        return Err(ApiError::from_status(status.as_u16(), error_message(&error_text), retry_after));
+    }
+    Ok(response)
+}
@@ -1,38 +1,250 @@
 use tauri::{State, Window, Emitter};
-use crate::commands::settings::AppState;
-use crate::agent::r#loop::Agent;
-use crate::api::deepseek::Message;
+use crate::commands::settings::{context_budget, load_fallback_clients, load_generation_params, AppState};
+use crate::commands::session::{load_session_budget, load_session_messages, load_session_params, DbState};
+use crate::agent::budget::TaskBudget;
+use crate::agent::context::HistorySummary;
+use crate::tools::sandbox::Workspace;
+use crate::agent::r#loop::{Agent, AgentEvent};
+use crate::agent::permission::ApprovalResponse;
+use crate::agent::registry::ToolRegistry;
+use crate::api::types::Message;
+use crate::api::LlmBackend;
+use crate::db::Database;
+use std::collections::HashMap;
+use std::sync::Arc;
 use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn send_message(
     window: Window,
     state: State<'_, AppState>,
+    db_state: State<'_, DbState>,
     message: String,
     history: Vec<Message>,
+    session_id: Option<String>,
+    env: Option<HashMap<String, String>>,
+    budget: Option<TaskBudget>,
 ) -> Result<(), String> {
+    run_agent(window, &state, &db_state, Some(message), history, session_id, env, budget).await
+}
+
+/// Resumes a task of the session that stopped on its budget, from the saved history.
+#[tauri::command]
+pub async fn continue_task(
+    window: Window,
+    state: State<'_, AppState>,
+    db_state: State<'_, DbState>,
+    session_id: String,
+    env: Option<HashMap<String, String>>,
+    budget: Option<TaskBudget>,
+) -> Result<(), String> {
+    let history = load_session_messages(&db_state.db, &session_id)?;
+    // A final assistant message means the task already finished
+    if !matches!(history.last().map(|m| m.role.as_str()), Some("user") | Some("tool")) {
+        return Err("Nothing to continue in this session".to_string());
+    }
+    run_agent(window, &state, &db_state, None, history, Some(session_id), env, budget).await
+}
+
+// Agent bound to the session's project folder: tools are confined to it and
+// relative paths, including the shell's working directory, start there.
+// Without a folder they would see the whole filesystem from the app's own
+// cwd, so the task is refused instead
+fn session_agent<B: LlmBackend>(
+    backend: B,
+    registry: Arc<ToolRegistry>,
+    db: &Database,
+    session_id: Option<String>,
+    env: HashMap<String, String>,
+) -> Result<Agent<B>, String> {
+    let root = match &session_id {
+        Some(id) => db.get_session(id).map_err(|e| e.to_string())?.and_then(|s| s.workspace_root),
+        None => None,
+    };
+    let workspace = Workspace::new(root.ok_or("Choose a project folder for this session first")?)?;
+    Ok(Agent::new(backend, registry)
+        .with_session(session_id)
+        .with_workspace(workspace)
+        .with_env(env))
+}
+
+// Runs the agent on `task`, or continues `history` when there is none,
+// forwarding its events to the frontend until it finishes
+#[allow(clippy::too_many_arguments)]
+async fn run_agent(
+    window: Window,
+    state: &AppState,
+    db_state: &DbState,
+    task: Option<String>,
+    history: Vec<Message>,
+    session_id: Option<String>,
+    env: Option<HashMap<String, String>>,
+    request_budget: Option<TaskBudget>,
+) -> Result<(), String> {
+    // Use the client for the provider selected in settings
     let client = {
-        let guard = state.client.lock().map_err(|_| "Failed to lock state")?;
+        let guard = state.unified_client.lock().map_err(|_| "Failed to lock state")?;
         guard.as_ref().ok_or("API Key not set")?.clone()
     };
 
-    let registry = state.registry.clone();
-    let agent = Agent::new(client, registry);
+    // Session parameters override the global ones field by field
+    let mut params = load_generation_params();
+    if let Some(id) = &session_id {
+        if let Some(overrides) = load_session_params(&db_state.db, id)? {
+            params = params.overridden_by(&overrides);
+        }
+    }
+    // Fallbacks get the same parameters but the context budget of their own model
+    let fallbacks = load_fallback_clients(&client)
+        .into_iter()
+        .map(|fallback| {
+            let budget = context_budget(fallback.provider(), fallback.model_name());
+            (fallback.with_params(params.clone()), budget)
+        })
+        .collect();
+    let client = client.with_params(params);
+    let budget = context_budget(client.provider(), client.model_name());
+
+    // Reuse the summary an earlier task wrote when it compacted the history
+    let summary: Option<HistorySummary> = match &session_id {
+        Some(id) => db_state
+            .db
+            .get_session_summary(id)
+            .map_err(|e| e.to_string())?
+            .and_then(|json| serde_json::from_str(&json).ok()),
+        None => None,
+    };
+
+    // A per-request budget overrides the session's limits
+    let mut task_budget = match &session_id {
+        Some(id) => load_session_budget(&db_state.db, id)?.unwrap_or_default(),
+        None => TaskBudget::default(),
+    };
+    if let Some(overrides) = request_budget {
+        overrides.validate()?;
+        task_budget = task_budget.overridden_by(&overrides);
+    }
+
+    let agent = session_agent(client, state.registry.clone(), &db_state.db, session_id.clone(), env.unwrap_or_default())?
+        .with_approvals(state.approvals.clone())
+        .with_context_budget(budget)
+        .with_summary(summary)
+        .with_fallbacks(fallbacks)
+        .with_budget(task_budget);
+
+    // Register the task so it can be cancelled from the frontend
+    let task_id = uuid::Uuid::new_v4().to_string();
+    let cancel = CancellationToken::new();
+    state
+        .tasks
+        .lock()
+        .map_err(|_| "Failed to lock tasks")?
+        .insert(task_id.clone(), cancel.clone());
 
     let (tx, mut rx) = mpsc::channel(100);
+    let _ = tx.send(AgentEvent::TaskStarted { task_id: task_id.clone() }).await;
 
     // Spawn agent task
     tokio::spawn(async move {
-        agent.run_task(message, history, tx).await;
+        match task {
+            Some(task) => agent.run_task(task, history, tx, cancel).await,
+            None => agent.continue_task(history, tx, cancel).await,
+        }
     });
 
     // Forward events to frontend
     while let Some(event) = rx.recv().await {
+        if let (AgentEvent::Usage { model, usage, cost }, Some(id)) = (&event, &session_id) {
+            if let Err(e) = db_state.db.add_usage(id, model, usage, *cost) {
+                eprintln!("Failed to record usage: {}", e);
+            }
+        }
+        if let (AgentEvent::HistoryCompacted(summary), Some(id)) = (&event, &session_id) {
+            let json = serde_json::to_string(summary).map_err(|e| e.to_string())?;
+            if let Err(e) = db_state.db.update_session_summary(id, Some(&json)) {
+                eprintln!("Failed to save history summary: {}", e);
+            }
+        }
         // Tauri v2 uses .emit() on Window
         if let Err(e) = window.emit("agent-event", event) {
             eprintln!("Failed to emit event: {}", e);
         }
     }
 
+    if let Ok(mut tasks) = state.tasks.lock() {
+        tasks.remove(&task_id);
+    }
+
     Ok(())
 }
+
+#[tauri::command]
+pub fn cancel_task(state: State<'_, AppState>, task_id: String) -> Result<(), String> {
+    let tasks = state.tasks.lock().map_err(|_| "Failed to lock tasks")?;
+    let cancel = tasks.get(&task_id).ok_or("Task not found")?;
+    cancel.cancel();
+    Ok(())
+}
+
+#[tauri::command]
+pub fn respond_to_approval(
+    state: State<'_, AppState>,
+    request_id: String,
+    response: ApprovalResponse,
+) -> Result<(), String> {
+    state.approvals.respond(&request_id, response)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::api::scripted::{finish_chunk, tool_call_chunk, ScriptedBackend, ScriptedTurn};
+    use crate::tools::bash::BashTool;
+    use crate::tools::file::ReadFileTool;
+
+    fn tool_turn(id: &str, name: &str, args: &str) -> ScriptedTurn {
+        ScriptedTurn::Chunks(vec![Ok(tool_call_chunk(0, Some(id), Some(name), Some(args))), Ok(finish_chunk("tool_calls"))])
+    }
+
+    #[tokio::test]
+    async fn tools_run_in_the_session_project_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "project notes").unwrap();
+        let db = Database::open_in_memory().unwrap();
+        let session = db.create_session("t", Some(&dir.path().to_string_lossy())).unwrap();
+        let mut registry = ToolRegistry::new();
+        registry.register(ReadFileTool);
+        registry.register(BashTool::default());
+        let backend = Arc::new(ScriptedBackend::new(vec![
+            tool_turn("call_1", "read_file", r#"{"path":"notes.txt"}"#),
+            tool_turn("call_2", "bash", r#"{"command":"pwd"}"#),
+            ScriptedBackend::text("done"),
+        ]));
+
+        let agent = session_agent(backend, Arc::new(registry), &db, Some(session.id), HashMap::new()).unwrap();
+        let (tx, mut rx) = mpsc::channel(100);
+        agent.run_task("look around".to_string(), Vec::new(), tx, CancellationToken::new()).await;
+        let mut results = Vec::new();
+        while let Some(event) = rx.recv().await {
+            if let AgentEvent::ToolResult { result, .. } = event {
+                results.push(result);
+            }
+        }
+
+        let root = std::fs::canonicalize(dir.path()).unwrap();
+        assert!(results[0].contains("project notes"), "{}", results[0]);
+        assert_eq!(results[1].trim(), root.to_string_lossy());
+    }
+
+    #[test]
+    fn refuses_sessions_without_project_folder() {
+        let db = Database::open_in_memory().unwrap();
+        let session = db.create_session("t", None).unwrap();
+        let backend = Arc::new(ScriptedBackend::new(Vec::new()));
+
+        let result = session_agent(backend, Arc::new(ToolRegistry::new()), &db, Some(session.id), HashMap::new());
+        assert!(result.is_err());
+    }
+}
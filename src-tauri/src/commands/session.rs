@@ -1,5 +1,8 @@
-use crate::api::deepseek::Message;
-use crate::db::{Database, Session};
+use crate::agent::budget::TaskBudget;
+use crate::api::types::{GenerationParams, Message};
+use crate::commands::settings::AppState;
+use crate::db::{Database, PermissionRule, Session, SessionUsage};
+use crate::tools::sandbox::Workspace;
 use std::sync::Arc;
 use tauri::State;
 
@@ -8,8 +11,16 @@ pub struct DbState {
 }
 
 #[tauri::command]
-pub fn create_session(state: State<'_, DbState>, title: String) -> Result<Session, String> {
-    state.db.create_session(&title).map_err(|e| e.to_string())
+pub fn create_session(
+    state: State<'_, DbState>,
+    title: String,
+    workspace_root: Option<String>,
+) -> Result<Session, String> {
+    let workspace_root = workspace_root.map(|root| canonical_root(&root)).transpose()?;
+    state
+        .db
+        .create_session(&title, workspace_root.as_deref())
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -35,7 +46,104 @@ pub fn update_session_title(
 }
 
 #[tauri::command]
-pub fn delete_session(state: State<'_, DbState>, id: String) -> Result<(), String> {
+pub fn set_session_workspace(
+    state: State<'_, DbState>,
+    id: String,
+    workspace_root: Option<String>,
+) -> Result<(), String> {
+    let workspace_root = workspace_root.map(|root| canonical_root(&root)).transpose()?;
+    state
+        .db
+        .update_session_workspace(&id, workspace_root.as_deref())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_session_usage(state: State<'_, DbState>, id: String) -> Result<SessionUsage, String> {
+    state.db.get_session_usage(&id).map_err(|e| e.to_string())
+}
+
+/// Generation parameters overriding the global ones for this session.
+#[tauri::command]
+pub fn get_session_params(
+    state: State<'_, DbState>,
+    id: String,
+) -> Result<Option<GenerationParams>, String> {
+    load_session_params(&state.db, &id)
+}
+
+#[tauri::command]
+pub fn set_session_params(
+    state: State<'_, DbState>,
+    id: String,
+    params: Option<GenerationParams>,
+) -> Result<(), String> {
+    let json = match params {
+        Some(params) => {
+            params.validate()?;
+            Some(serde_json::to_string(&params).map_err(|e| e.to_string())?)
+        },
+        None => None,
+    };
+    state
+        .db
+        .update_session_params(&id, json.as_deref())
+        .map_err(|e| e.to_string())
+}
+
+/// Task limits overriding the defaults for this session.
+#[tauri::command]
+pub fn get_session_budget(
+    state: State<'_, DbState>,
+    id: String,
+) -> Result<Option<TaskBudget>, String> {
+    load_session_budget(&state.db, &id)
+}
+
+#[tauri::command]
+pub fn set_session_budget(
+    state: State<'_, DbState>,
+    id: String,
+    budget: Option<TaskBudget>,
+) -> Result<(), String> {
+    let json = match budget {
+        Some(budget) => {
+            budget.validate()?;
+            Some(serde_json::to_string(&budget).map_err(|e| e.to_string())?)
+        },
+        None => None,
+    };
+    state
+        .db
+        .update_session_budget(&id, json.as_deref())
+        .map_err(|e| e.to_string())
+}
+
+pub fn load_session_budget(db: &Database, id: &str) -> Result<Option<TaskBudget>, String> {
+    let json = db.get_session_budget(id).map_err(|e| e.to_string())?;
+    json.map(|json| serde_json::from_str(&json).map_err(|e| e.to_string()))
+        .transpose()
+}
+
+pub fn load_session_params(db: &Database, id: &str) -> Result<Option<GenerationParams>, String> {
+    let json = db.get_session_params(id).map_err(|e| e.to_string())?;
+    json.map(|json| serde_json::from_str(&json).map_err(|e| e.to_string()))
+        .transpose()
+}
+
+// Validate the directory up front so a bad root is reported when it is set
+fn canonical_root(root: &str) -> Result<String, String> {
+    Workspace::new(root).map(|ws| ws.root().to_string_lossy().to_string())
+}
+
+#[tauri::command]
+pub fn delete_session(
+    state: State<'_, DbState>,
+    app_state: State<'_, AppState>,
+    id: String,
+) -> Result<(), String> {
+    app_state.processes.kill_session(&id);
+    app_state.shells.remove(&id);
     state.db.delete_session(&id).map_err(|e| e.to_string())
 }
 
@@ -44,9 +152,13 @@ pub fn get_session_messages(
     state: State<'_, DbState>,
     session_id: String,
 ) -> Result<Vec<Message>, String> {
-    let db_messages = state
-        .db
-        .get_messages(&session_id)
+    load_session_messages(&state.db, &session_id)
+}
+
+/// The session's saved messages in API format.
+pub fn load_session_messages(db: &Database, session_id: &str) -> Result<Vec<Message>, String> {
+    let db_messages = db
+        .get_messages(session_id)
         .map_err(|e| e.to_string())?;
 
     // Convert SessionMessage to API Message format
@@ -60,6 +172,7 @@ pub fn get_session_messages(
                 tool_calls: m.tool_calls.and_then(|tc| serde_json::from_str(&tc).ok()),
                 tool_call_id: m.tool_call_id,
                 name: m.name,
+                reasoning_content: m.reasoning_content,
             }
         })
         .collect();
@@ -68,6 +181,7 @@ pub fn get_session_messages(
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub fn save_message(
     state: State<'_, DbState>,
     session_id: String,
@@ -76,6 +190,8 @@ pub fn save_message(
     tool_calls: Option<String>,
     tool_call_id: Option<String>,
     name: Option<String>,
+    reasoning_content: Option<String>,
+    model: Option<String>,
 ) -> Result<i64, String> {
     // Also update session timestamp
     state.db.touch_session(&session_id).ok();
@@ -89,6 +205,8 @@ pub fn save_message(
             tool_calls.as_deref(),
             tool_call_id.as_deref(),
             name.as_deref(),
+            reasoning_content.as_deref(),
+            model.as_deref(),
         )
         .map_err(|e| e.to_string())
 }
@@ -100,3 +218,22 @@ pub fn clear_session_messages(state: State<'_, DbState>, session_id: String) ->
         .clear_messages(&session_id)
         .map_err(|e| e.to_string())
 }
+
+#[tauri::command]
+pub fn list_permission_rules(
+    state: State<'_, DbState>,
+    session_id: String,
+) -> Result<Vec<PermissionRule>, String> {
+    state
+        .db
+        .get_permission_rules(&session_id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn delete_permission_rule(state: State<'_, DbState>, id: i64) -> Result<(), String> {
+    state
+        .db
+        .delete_permission_rule(id)
+        .map_err(|e| e.to_string())
+}
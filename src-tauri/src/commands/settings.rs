@@ -1,28 +1,58 @@
 use keyring::Entry;
 use tauri::State;
-use crate::api::{UnifiedLLMClient, ModelConfig, ModelProvider};
-use crate::api::deepseek::DeepSeekClient;
+use crate::api::{CustomEndpoint, UnifiedLLMClient, ModelConfig, ModelProvider};
+use crate::api::models::{catalog_models, lookup, merge_with_catalog, ModelInfo};
+use crate::agent::context::ContextBudget;
+use crate::api::types::{GenerationParams, Message};
+use crate::agent::permission::Approvals;
 use crate::agent::registry::ToolRegistry;
+use crate::tools::process::ProcessTable;
+use crate::tools::shell::ShellSessions;
+use std::collections::HashMap;
 use std::sync::{Mutex, Arc};
+use tokio_util::sync::CancellationToken;
 use serde::{Deserialize, Serialize};
 
 const SERVICE_NAME: &str = "codemaster-app";
-const DEEPSEEK_KEY: &str = "deepseek-api-key";
-const QWEN_KEY: &str = "qwen-api-key";
 const MODEL_PROVIDER_KEY: &str = "model-provider";
+const CUSTOM_ENDPOINT_KEY: &str = "custom-endpoint";
+const GENERATION_PARAMS_KEY: &str = "generation-params";
+const CONTEXT_BUDGETS_KEY: &str = "context-budgets";
+const FALLBACK_CHAIN_KEY: &str = "fallback-chain";
+
+/// A provider to fail over to, with the model to use there.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Fallback {
+    pub provider: String,
+    /// None uses the provider's chosen or default model
+    pub model: Option<String>,
+}
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct ModelSettings {
     pub provider: String,
-    pub deepseek_key: Option<String>,
-    pub qwen_key: Option<String>,
+    /// Saved API keys by provider id
+    pub api_keys: HashMap<String, String>,
+    /// Chosen models by provider id; providers without one use their default
+    pub models: HashMap<String, String>,
+    pub custom_endpoint: Option<CustomEndpoint>,
+    /// Context window overrides by model id
+    pub context_budgets: HashMap<String, u32>,
+    /// Tried in order when the selected provider keeps failing
+    pub fallbacks: Vec<Fallback>,
 }
 
 pub struct AppState {
-    pub client: Mutex<Option<DeepSeekClient>>,
     pub unified_client: Mutex<Option<UnifiedLLMClient>>,
     pub registry: Arc<ToolRegistry>,
     pub current_provider: Mutex<ModelProvider>,
+    /// Cancellation tokens of running agent tasks, keyed by task id
+    pub tasks: Mutex<HashMap<String, CancellationToken>>,
+    pub approvals: Arc<Approvals>,
+    /// Background processes started by the agent
+    pub processes: Arc<ProcessTable>,
+    /// Session shells of the bash tool
+    pub shells: Arc<ShellSessions>,
 }
 
 fn get_key(name: &str) -> Option<String> {
@@ -36,51 +66,172 @@ fn set_key(name: &str, value: &str) -> Result<(), String> {
     entry.set_password(value).map_err(|e| e.to_string())
 }
 
-#[tauri::command]
-pub fn set_api_key(state: State<'_, AppState>, api_key: String) -> Result<(), String> {
-    // Legacy: save as DeepSeek key
-    set_key(DEEPSEEK_KEY, &api_key)?;
+fn delete_key(name: &str) -> Result<(), String> {
+    let entry = Entry::new(SERVICE_NAME, name).map_err(|e| e.to_string())?;
+    match entry.delete_password() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
 
-    // Update the client in state
-    let mut client_lock = state.client.lock().map_err(|_| "Failed to lock state")?;
-    *client_lock = Some(DeepSeekClient::new(api_key.clone()));
+// Keyring entry holding the chosen model of a built-in provider
+fn model_key(provider: &ModelProvider) -> String {
+    format!("{}-model", provider.id())
+}
 
-    // Also update unified client
-    let mut unified_lock = state.unified_client.lock().map_err(|_| "Failed to lock unified state")?;
-    *unified_lock = Some(UnifiedLLMClient::new(ModelConfig::deepseek(api_key)));
+// Every provider id from the frontend or the keyring goes through here;
+// `from_id` alone would quietly turn an unknown id into DeepSeek
+fn known_provider(id: &str) -> Result<ModelProvider, String> {
+    let provider = ModelProvider::from_id(id);
+    if provider.id() != id {
+        return Err(format!("Unknown provider: {}", id));
+    }
+    Ok(provider)
+}
 
-    Ok(())
+/// Provider selected in settings; the default when none or an unknown one is saved.
+pub fn saved_provider() -> ModelProvider {
+    match get_key(MODEL_PROVIDER_KEY) {
+        Some(id) => known_provider(&id).unwrap_or_else(|e| {
+            eprintln!("{}, using {}", e, ModelProvider::default().display_name());
+            ModelProvider::default()
+        }),
+        None => ModelProvider::default(),
+    }
 }
 
-#[tauri::command]
-pub fn get_api_key() -> Result<String, String> {
-    get_key(DEEPSEEK_KEY).ok_or_else(|| "No API key found".to_string())
+fn get_custom_endpoint() -> Option<CustomEndpoint> {
+    get_key(CUSTOM_ENDPOINT_KEY).and_then(|json| serde_json::from_str(&json).ok())
 }
 
-#[tauri::command]
-pub async fn test_connection(api_key: String) -> Result<String, String> {
-    let client = DeepSeekClient::new(api_key);
-    let messages = vec![crate::api::deepseek::Message {
+/// Builds the client config for `provider` from the saved keys and endpoint.
+pub fn load_model_config(provider: &ModelProvider) -> Result<ModelConfig, String> {
+    match provider {
+        ModelProvider::Custom => {
+            let endpoint = get_custom_endpoint().ok_or("Custom endpoint is not configured")?;
+            ModelConfig::custom(endpoint, get_key(provider.key_name()))
+        },
+        _ => {
+            let key = get_key(provider.key_name())
+                .ok_or_else(|| format!("{} API key not set", provider.display_name()))?;
+            Ok(ModelConfig::for_provider(provider, key)?.with_model(get_key(&model_key(provider))))
+        },
+    }
+}
+
+fn get_context_budgets() -> HashMap<String, u32> {
+    get_key(CONTEXT_BUDGETS_KEY)
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+/// Context budget of a model: the saved override, else the catalog's window.
+pub fn context_budget(provider: &ModelProvider, model: &str) -> ContextBudget {
+    let window = get_context_budgets()
+        .get(model)
+        .copied()
+        .or_else(|| lookup(provider, model).map(|e| e.context_window))
+        .unwrap_or(ContextBudget::DEFAULT_WINDOW);
+    ContextBudget::new(window)
+}
+
+fn get_fallbacks() -> Vec<Fallback> {
+    get_key(FALLBACK_CHAIN_KEY)
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+/// Clients of the fallback chain, skipping entries that are not configured
+/// or would just repeat `primary`.
+pub fn load_fallback_clients(primary: &UnifiedLLMClient) -> Vec<UnifiedLLMClient> {
+    get_fallbacks()
+        .into_iter()
+        .filter_map(|fallback| {
+            let config = match known_provider(&fallback.provider).and_then(|provider| load_model_config(&provider)) {
+                Ok(config) => config.with_model(fallback.model),
+                Err(e) => {
+                    eprintln!("Skipping fallback {}: {}", fallback.provider, e);
+                    return None;
+                },
+            };
+            let client = UnifiedLLMClient::new(config);
+            let same = client.provider() == primary.provider() && client.model_name() == primary.model_name();
+            (!same).then_some(client)
+        })
+        .collect()
+}
+
+/// Generation parameters applied to every session that does not override them.
+pub fn load_generation_params() -> GenerationParams {
+    get_key(GENERATION_PARAMS_KEY)
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+// Sends a minimal request to check that the key and endpoint work
+async fn ping(config: ModelConfig) -> Result<(), String> {
+    let messages = vec![Message {
         role: "user".to_string(),
         content: Some("ping".to_string()),
         tool_calls: None,
         tool_call_id: None,
         name: None,
+        reasoning_content: None,
     }];
-    
-    match client.chat_completion(messages, None).await {
-        Ok(_) => Ok("Connection successful".to_string()),
-        Err(e) => Err(e.to_string()),
+    UnifiedLLMClient::new(config)
+        .chat_completion(messages, None)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn set_api_key(state: State<'_, AppState>, api_key: String) -> Result<(), String> {
+    // Legacy: save as DeepSeek key
+    set_key(ModelProvider::DeepSeek.key_name(), &api_key)?;
+
+    // Update the client, unless another provider is selected
+    let provider = state.current_provider.lock().map_err(|_| "Failed to lock")?.clone();
+    if provider == ModelProvider::DeepSeek {
+        let mut unified_lock = state.unified_client.lock().map_err(|_| "Failed to lock unified state")?;
+        let config = ModelConfig::deepseek(api_key).with_model(get_key(&model_key(&provider)));
+        *unified_lock = Some(UnifiedLLMClient::new(config));
     }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_api_key() -> Result<String, String> {
+    get_key(ModelProvider::DeepSeek.key_name()).ok_or_else(|| "No API key found".to_string())
+}
+
+#[tauri::command]
+pub async fn test_connection(api_key: String) -> Result<String, String> {
+    ping(ModelConfig::deepseek(api_key)).await?;
+    Ok("Connection successful".to_string())
 }
 
 // New multi-model commands
 #[tauri::command]
 pub fn get_model_settings() -> Result<ModelSettings, String> {
+    let api_keys = ModelProvider::BUILTIN
+        .iter()
+        .chain([&ModelProvider::Custom])
+        .filter_map(|p| get_key(p.key_name()).map(|key| (p.id().to_string(), key)))
+        .collect();
+    let models = ModelProvider::BUILTIN
+        .iter()
+        .filter_map(|p| get_key(&model_key(p)).map(|model| (p.id().to_string(), model)))
+        .collect();
+
     Ok(ModelSettings {
-        provider: get_key(MODEL_PROVIDER_KEY).unwrap_or_else(|| "deepseek".to_string()),
-        deepseek_key: get_key(DEEPSEEK_KEY),
-        qwen_key: get_key(QWEN_KEY),
+        provider: saved_provider().id().to_string(),
+        api_keys,
+        models,
+        custom_endpoint: get_custom_endpoint(),
+        context_budgets: get_context_budgets(),
+        fallbacks: get_fallbacks(),
     })
 }
 
@@ -88,83 +239,141 @@ pub fn get_model_settings() -> Result<ModelSettings, String> {
 pub fn set_model_settings(
     state: State<'_, AppState>,
     provider: String,
-    deepseek_key: Option<String>,
-    qwen_key: Option<String>,
+    api_keys: Option<HashMap<String, String>>,
+    models: Option<HashMap<String, String>>,
+    custom_endpoint: Option<CustomEndpoint>,
+    context_budgets: Option<HashMap<String, u32>>,
+    fallbacks: Option<Vec<Fallback>>,
 ) -> Result<(), String> {
-    // Save keys
-    if let Some(key) = &deepseek_key {
-        set_key(DEEPSEEK_KEY, key)?;
+    // An unknown id would silently fall back to the default provider on load
+    let model_provider = known_provider(&provider)?;
+
+    // Save keys and models; an empty value removes the saved one
+    for (id, key) in api_keys.unwrap_or_default() {
+        let key_name = known_provider(&id)?.key_name();
+        if key.is_empty() {
+            delete_key(key_name)?;
+        } else {
+            set_key(key_name, &key)?;
+        }
+    }
+    for (id, model) in models.unwrap_or_default() {
+        let key_name = model_key(&known_provider(&id)?);
+        if model.trim().is_empty() {
+            delete_key(&key_name)?;
+        } else {
+            set_key(&key_name, model.trim())?;
+        }
     }
-    if let Some(key) = &qwen_key {
-        set_key(QWEN_KEY, key)?;
+    if let Some(endpoint) = custom_endpoint {
+        // Validate before saving so a broken endpoint is reported right away
+        ModelConfig::custom(endpoint.clone(), None)?;
+        let json = serde_json::to_string(&endpoint).map_err(|e| e.to_string())?;
+        set_key(CUSTOM_ENDPOINT_KEY, &json)?;
+    }
+    if let Some(budgets) = context_budgets {
+        // Anything below a few thousand tokens cannot even hold the system prompt and tools
+        if let Some((model, _)) = budgets.iter().find(|(_, &tokens)| tokens < 4_096) {
+            return Err(format!("Context budget of {} must be at least 4096 tokens", model));
+        }
+        let json = serde_json::to_string(&budgets).map_err(|e| e.to_string())?;
+        set_key(CONTEXT_BUDGETS_KEY, &json)?;
+    }
+    if let Some(fallbacks) = fallbacks {
+        for fallback in &fallbacks {
+            known_provider(&fallback.provider)?;
+        }
+        if fallbacks.is_empty() {
+            delete_key(FALLBACK_CHAIN_KEY)?;
+        } else {
+            let json = serde_json::to_string(&fallbacks).map_err(|e| e.to_string())?;
+            set_key(FALLBACK_CHAIN_KEY, &json)?;
+        }
     }
     set_key(MODEL_PROVIDER_KEY, &provider)?;
 
-    // Update provider
-    let model_provider = match provider.as_str() {
-        "qwen" => ModelProvider::Qwen,
-        _ => ModelProvider::DeepSeek,
-    };
-
     // Update current provider
     {
         let mut provider_lock = state.current_provider.lock().map_err(|_| "Failed to lock")?;
         *provider_lock = model_provider.clone();
     }
 
-    // Update unified client based on selected provider
-    let config = match model_provider {
-        ModelProvider::Qwen => {
-            let key = qwen_key.or_else(|| get_key(QWEN_KEY))
-                .ok_or("Qwen API key not set")?;
-            ModelConfig::qwen(key)
-        },
-        ModelProvider::DeepSeek => {
-            let key = deepseek_key.clone().or_else(|| get_key(DEEPSEEK_KEY))
-                .ok_or("DeepSeek API key not set")?;
-            ModelConfig::deepseek(key)
-        },
-    };
-
+    // Update the client based on selected provider
+    let config = load_model_config(&model_provider)?;
     let mut unified_lock = state.unified_client.lock().map_err(|_| "Failed to lock")?;
     *unified_lock = Some(UnifiedLLMClient::new(config));
 
-    // Also update legacy client for backward compatibility
-    if let Some(key) = deepseek_key.or_else(|| get_key(DEEPSEEK_KEY)) {
-        let mut client_lock = state.client.lock().map_err(|_| "Failed to lock")?;
-        *client_lock = Some(DeepSeekClient::new(key));
-    }
-
     Ok(())
 }
 
+#[tauri::command]
+pub fn get_generation_params() -> GenerationParams {
+    load_generation_params()
+}
+
+#[tauri::command]
+pub fn set_generation_params(params: GenerationParams) -> Result<(), String> {
+    params.validate()?;
+    if params == GenerationParams::default() {
+        return delete_key(GENERATION_PARAMS_KEY);
+    }
+    let json = serde_json::to_string(&params).map_err(|e| e.to_string())?;
+    set_key(GENERATION_PARAMS_KEY, &json)
+}
+
 #[tauri::command]
 pub fn get_current_provider(state: State<'_, AppState>) -> Result<String, String> {
     let provider = state.current_provider.lock().map_err(|_| "Failed to lock")?;
-    Ok(match *provider {
-        ModelProvider::DeepSeek => "deepseek".to_string(),
-        ModelProvider::Qwen => "qwen".to_string(),
-    })
+    Ok(provider.id().to_string())
 }
 
 #[tauri::command]
-pub async fn test_model_connection(provider: String, api_key: String) -> Result<String, String> {
-    let config = match provider.as_str() {
-        "qwen" => ModelConfig::qwen(api_key),
-        _ => ModelConfig::deepseek(api_key),
+pub async fn test_model_connection(
+    provider: String,
+    api_key: Option<String>,
+    model: Option<String>,
+    custom_endpoint: Option<CustomEndpoint>,
+) -> Result<String, String> {
+    let config = match known_provider(&provider)? {
+        ModelProvider::Custom => {
+            let endpoint = custom_endpoint.ok_or("Custom endpoint is not configured")?;
+            ModelConfig::custom(endpoint, api_key.filter(|k| !k.is_empty()))?
+        },
+        builtin => ModelConfig::for_provider(&builtin, api_key.ok_or("API key is required")?)?.with_model(model),
     };
-    
-    let client = UnifiedLLMClient::new(config);
-    let messages = vec![crate::api::deepseek::Message {
-        role: "user".to_string(),
-        content: Some("ping".to_string()),
-        tool_calls: None,
-        tool_call_id: None,
-        name: None,
-    }];
-    
-    match client.chat_completion(messages, None).await {
-        Ok(_) => Ok(format!("{} connection successful", provider)),
-        Err(e) => Err(e.to_string()),
+    ping(config).await?;
+    Ok(format!("{} connection successful", provider))
+}
+
+/// Models the provider offers. Falls back to the built-in catalog when the
+/// provider's `/models` endpoint is unavailable or no key is configured.
+#[tauri::command]
+pub async fn list_models(
+    provider: String,
+    api_key: Option<String>,
+    custom_endpoint: Option<CustomEndpoint>,
+) -> Result<Vec<ModelInfo>, String> {
+    let model_provider = known_provider(&provider)?;
+    let api_key = api_key.filter(|k| !k.is_empty()).or_else(|| get_key(model_provider.key_name()));
+
+    let config = match &model_provider {
+        ModelProvider::Custom => {
+            let endpoint = custom_endpoint.or_else(get_custom_endpoint).ok_or("Custom endpoint is not configured")?;
+            Some(ModelConfig::custom(endpoint, api_key)?)
+        },
+        builtin => api_key.map(|key| ModelConfig::for_provider(builtin, key)).transpose()?,
+    };
+    let Some(config) = config else {
+        return Ok(catalog_models(&model_provider));
+    };
+
+    match UnifiedLLMClient::new(config).list_models().await {
+        Ok(ids) if !ids.is_empty() => Ok(merge_with_catalog(&model_provider, &ids)),
+        Ok(_) => Ok(catalog_models(&model_provider)),
+        Err(e) if model_provider == ModelProvider::Custom => Err(e.to_string()),
+        Err(e) => {
+            eprintln!("Failed to list {} models, using catalog: {}", model_provider.display_name(), e);
+            Ok(catalog_models(&model_provider))
+        },
     }
 }
@@ -3,6 +3,7 @@ use rusqlite::{params, Connection, Result};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::sync::Mutex;
+use crate::api::types::Usage;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Session {
@@ -10,6 +11,7 @@ pub struct Session {
     pub title: String,
     pub created_at: i64,
     pub updated_at: i64,
+    pub workspace_root: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -21,6 +23,29 @@ pub struct SessionMessage {
     pub tool_calls: Option<String>, // JSON string
     pub tool_call_id: Option<String>,
     pub name: Option<String>,
+    pub reasoning_content: Option<String>,
+    /// Model that produced an assistant message
+    pub model: Option<String>,
+    pub created_at: i64,
+}
+
+/// Token usage summed over the requests of a session.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SessionUsage {
+    pub requests: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub cached_tokens: i64,
+    /// In CNY; requests to models without known prices are not counted
+    pub cost: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PermissionRule {
+    pub id: i64,
+    pub session_id: String,
+    pub tool_name: String,
+    pub command_prefix: Option<String>, // None allows every call of the tool
     pub created_at: i64,
 }
 
@@ -38,7 +63,15 @@ impl Database {
         }
 
         let conn = Connection::open(&path)?;
+        Self::init(conn)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
 
+    fn init(conn: Connection) -> Result<Self> {
         // Initialize tables
         conn.execute_batch(
             "
@@ -62,14 +95,60 @@ impl Database {
             );
             
             CREATE INDEX IF NOT EXISTS idx_messages_session ON messages(session_id);
+
+            CREATE TABLE IF NOT EXISTS permission_rules (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                session_id TEXT NOT NULL,
+                tool_name TEXT NOT NULL,
+                command_prefix TEXT,
+                created_at INTEGER NOT NULL,
+                FOREIGN KEY (session_id) REFERENCES sessions(id) ON DELETE CASCADE
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_permission_rules_session ON permission_rules(session_id);
+
+            CREATE TABLE IF NOT EXISTS usage (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                session_id TEXT NOT NULL,
+                model TEXT NOT NULL,
+                prompt_tokens INTEGER NOT NULL,
+                completion_tokens INTEGER NOT NULL,
+                cached_tokens INTEGER NOT NULL,
+                cost REAL,
+                created_at INTEGER NOT NULL,
+                FOREIGN KEY (session_id) REFERENCES sessions(id) ON DELETE CASCADE
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_usage_session ON usage(session_id);
             ",
         )?;
 
+        // Columns added after the first release
+        Self::add_column_if_missing(&conn, "sessions", "workspace_root", "TEXT")?;
+        Self::add_column_if_missing(&conn, "messages", "reasoning_content", "TEXT")?;
+        Self::add_column_if_missing(&conn, "sessions", "generation_params", "TEXT")?;
+        Self::add_column_if_missing(&conn, "sessions", "history_summary", "TEXT")?;
+        Self::add_column_if_missing(&conn, "messages", "model", "TEXT")?;
+        Self::add_column_if_missing(&conn, "sessions", "task_budget", "TEXT")?;
+
         Ok(Self {
             conn: Mutex::new(conn),
         })
     }
 
+    fn add_column_if_missing(conn: &Connection, table: &str, column: &str, decl: &str) -> Result<()> {
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+        let exists = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .filter_map(|name| name.ok())
+            .any(|name| name == column);
+
+        if !exists {
+            conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl), [])?;
+        }
+        Ok(())
+    }
+
     fn get_db_path() -> PathBuf {
         // Store in user's app data directory
         let mut path = dirs::data_local_dir().unwrap_or_else(|| PathBuf::from("."));
@@ -79,14 +158,14 @@ impl Database {
     }
 
     // Session operations
-    pub fn create_session(&self, title: &str) -> Result<Session> {
+    pub fn create_session(&self, title: &str, workspace_root: Option<&str>) -> Result<Session> {
         let id = uuid::Uuid::new_v4().to_string();
         let now = chrono::Utc::now().timestamp();
 
         let conn = self.conn.lock().unwrap();
         conn.execute(
-            "INSERT INTO sessions (id, title, created_at, updated_at) VALUES (?1, ?2, ?3, ?4)",
-            params![id, title, now, now],
+            "INSERT INTO sessions (id, title, created_at, updated_at, workspace_root) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![id, title, now, now, workspace_root],
         )?;
 
         Ok(Session {
@@ -94,13 +173,14 @@ impl Database {
             title: title.to_string(),
             created_at: now,
             updated_at: now,
+            workspace_root: workspace_root.map(|s| s.to_string()),
         })
     }
 
     pub fn get_session(&self, id: &str) -> Result<Option<Session>> {
         let conn = self.conn.lock().unwrap();
         let mut stmt =
-            conn.prepare("SELECT id, title, created_at, updated_at FROM sessions WHERE id = ?1")?;
+            conn.prepare("SELECT id, title, created_at, updated_at, workspace_root FROM sessions WHERE id = ?1")?;
 
         let mut rows = stmt.query(params![id])?;
 
@@ -110,6 +190,7 @@ impl Database {
                 title: row.get(1)?,
                 created_at: row.get(2)?,
                 updated_at: row.get(3)?,
+                workspace_root: row.get(4)?,
             }))
         } else {
             Ok(None)
@@ -119,7 +200,7 @@ impl Database {
     pub fn list_sessions(&self) -> Result<Vec<Session>> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare(
-            "SELECT id, title, created_at, updated_at FROM sessions ORDER BY updated_at DESC",
+            "SELECT id, title, created_at, updated_at, workspace_root FROM sessions ORDER BY updated_at DESC",
         )?;
 
         let sessions = stmt.query_map([], |row| {
@@ -128,6 +209,7 @@ impl Database {
                 title: row.get(1)?,
                 created_at: row.get(2)?,
                 updated_at: row.get(3)?,
+                workspace_root: row.get(4)?,
             })
         })?;
 
@@ -144,10 +226,22 @@ impl Database {
         Ok(())
     }
 
+    pub fn update_session_workspace(&self, id: &str, workspace_root: Option<&str>) -> Result<()> {
+        let now = chrono::Utc::now().timestamp();
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE sessions SET workspace_root = ?1, updated_at = ?2 WHERE id = ?3",
+            params![workspace_root, now, id],
+        )?;
+        Ok(())
+    }
+
     pub fn delete_session(&self, id: &str) -> Result<()> {
         let conn = self.conn.lock().unwrap();
         // Delete messages first due to foreign key
         conn.execute("DELETE FROM messages WHERE session_id = ?1", params![id])?;
+        conn.execute("DELETE FROM permission_rules WHERE session_id = ?1", params![id])?;
+        conn.execute("DELETE FROM usage WHERE session_id = ?1", params![id])?;
         conn.execute("DELETE FROM sessions WHERE id = ?1", params![id])?;
         Ok(())
     }
@@ -162,7 +256,65 @@ impl Database {
         Ok(())
     }
 
+    /// Generation parameters of a session as JSON; `None` uses the global ones.
+    pub fn get_session_params(&self, id: &str) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT generation_params FROM sessions WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )
+    }
+
+    pub fn update_session_params(&self, id: &str, generation_params: Option<&str>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE sessions SET generation_params = ?1 WHERE id = ?2",
+            params![generation_params, id],
+        )?;
+        Ok(())
+    }
+
+    /// Task limits of the session as JSON, overriding the defaults.
+    pub fn get_session_budget(&self, id: &str) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT task_budget FROM sessions WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )
+    }
+
+    pub fn update_session_budget(&self, id: &str, task_budget: Option<&str>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE sessions SET task_budget = ?1 WHERE id = ?2",
+            params![task_budget, id],
+        )?;
+        Ok(())
+    }
+
+    /// Summary of the session's earlier turns as JSON, written by history compaction.
+    pub fn get_session_summary(&self, id: &str) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT history_summary FROM sessions WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )
+    }
+
+    pub fn update_session_summary(&self, id: &str, summary: Option<&str>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE sessions SET history_summary = ?1 WHERE id = ?2",
+            params![summary, id],
+        )?;
+        Ok(())
+    }
+
     // Message operations
+    #[allow(clippy::too_many_arguments)]
     pub fn add_message(
         &self,
         session_id: &str,
@@ -171,14 +323,16 @@ impl Database {
         tool_calls: Option<&str>,
         tool_call_id: Option<&str>,
         name: Option<&str>,
+        reasoning_content: Option<&str>,
+        model: Option<&str>,
     ) -> Result<i64> {
         let now = chrono::Utc::now().timestamp();
         let conn = self.conn.lock().unwrap();
 
         conn.execute(
-            "INSERT INTO messages (session_id, role, content, tool_calls, tool_call_id, name, created_at) 
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            params![session_id, role, content, tool_calls, tool_call_id, name, now],
+            "INSERT INTO messages (session_id, role, content, tool_calls, tool_call_id, name, reasoning_content, model, created_at) 
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![session_id, role, content, tool_calls, tool_call_id, name, reasoning_content, model, now],
         )?;
 
         Ok(conn.last_insert_rowid())
@@ -187,8 +341,8 @@ impl Database {
     pub fn get_messages(&self, session_id: &str) -> Result<Vec<SessionMessage>> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare(
-            "SELECT id, session_id, role, content, tool_calls, tool_call_id, name, created_at 
-             FROM messages WHERE session_id = ?1 ORDER BY created_at ASC",
+            "SELECT id, session_id, role, content, tool_calls, tool_call_id, name, reasoning_content, model, created_at 
+             FROM messages WHERE session_id = ?1 ORDER BY created_at ASC, id ASC",
         )?;
 
         let messages = stmt.query_map(params![session_id], |row| {
@@ -200,7 +354,9 @@ impl Database {
                 tool_calls: row.get(4)?,
                 tool_call_id: row.get(5)?,
                 name: row.get(6)?,
-                created_at: row.get(7)?,
+                reasoning_content: row.get(7)?,
+                model: row.get(8)?,
+                created_at: row.get(9)?,
             })
         })?;
 
@@ -213,6 +369,89 @@ impl Database {
             "DELETE FROM messages WHERE session_id = ?1",
             params![session_id],
         )?;
+        // The summary describes the deleted turns
+        conn.execute(
+            "UPDATE sessions SET history_summary = NULL WHERE id = ?1",
+            params![session_id],
+        )?;
         Ok(())
     }
+
+    // Permission rule operations
+    pub fn add_permission_rule(
+        &self,
+        session_id: &str,
+        tool_name: &str,
+        command_prefix: Option<&str>,
+    ) -> Result<i64> {
+        let now = chrono::Utc::now().timestamp();
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "INSERT INTO permission_rules (session_id, tool_name, command_prefix, created_at) 
+             VALUES (?1, ?2, ?3, ?4)",
+            params![session_id, tool_name, command_prefix, now],
+        )?;
+
+        Ok(conn.last_insert_rowid())
+    }
+
+    pub fn get_permission_rules(&self, session_id: &str) -> Result<Vec<PermissionRule>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, session_id, tool_name, command_prefix, created_at 
+             FROM permission_rules WHERE session_id = ?1 ORDER BY id ASC",
+        )?;
+
+        let rules = stmt.query_map(params![session_id], |row| {
+            Ok(PermissionRule {
+                id: row.get(0)?,
+                session_id: row.get(1)?,
+                tool_name: row.get(2)?,
+                command_prefix: row.get(3)?,
+                created_at: row.get(4)?,
+            })
+        })?;
+
+        rules.collect()
+    }
+
+    pub fn delete_permission_rule(&self, id: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM permission_rules WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    // Usage operations
+    pub fn add_usage(&self, session_id: &str, model: &str, usage: &Usage, cost: Option<f64>) -> Result<i64> {
+        let now = chrono::Utc::now().timestamp();
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "INSERT INTO usage (session_id, model, prompt_tokens, completion_tokens, cached_tokens, cost, created_at) 
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![session_id, model, usage.prompt_tokens, usage.completion_tokens, usage.cached_tokens, cost, now],
+        )?;
+
+        Ok(conn.last_insert_rowid())
+    }
+
+    pub fn get_session_usage(&self, session_id: &str) -> Result<SessionUsage> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(prompt_tokens), 0), COALESCE(SUM(completion_tokens), 0), 
+                    COALESCE(SUM(cached_tokens), 0), COALESCE(SUM(cost), 0) 
+             FROM usage WHERE session_id = ?1",
+            params![session_id],
+            |row| {
+                Ok(SessionUsage {
+                    requests: row.get(0)?,
+                    prompt_tokens: row.get(1)?,
+                    completion_tokens: row.get(2)?,
+                    cached_tokens: row.get(3)?,
+                    cost: row.get(4)?,
+                })
+            },
+        )
+    }
 }
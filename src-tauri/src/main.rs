@@ -6,55 +6,29 @@ mod tools;
 mod api;
 mod db;
 
+use std::collections::HashMap;
 use std::sync::{Mutex, Arc};
-use commands::settings::AppState;
+use commands::settings::{load_model_config, saved_provider, AppState};
 use commands::session::DbState;
-use keyring::Entry;
-use api::deepseek::DeepSeekClient;
-use api::{UnifiedLLMClient, ModelConfig, ModelProvider};
+use api::UnifiedLLMClient;
+use agent::permission::Approvals;
 use agent::registry::ToolRegistry;
 use tools::file::{ReadFileTool, WriteFileTool, EditFileTool};
 use tools::search::{GrepTool, GlobTool};
 use tools::bash::BashTool;
+use tools::shell::ShellSessions;
+use tools::process::{ProcessTable, StartProcessTool, ReadProcessOutputTool, SendProcessInputTool, KillProcessTool};
 use tools::project::ProjectStructureTool;
 use db::Database;
 
 fn main() {
     // Load saved provider preference
-    let saved_provider = Entry::new("codemaster-app", "model-provider")
-        .ok()
-        .and_then(|e| e.get_password().ok())
-        .unwrap_or_else(|| "deepseek".to_string());
-    
-    let current_provider = match saved_provider.as_str() {
-        "qwen" => ModelProvider::Qwen,
-        _ => ModelProvider::DeepSeek,
-    };
-
-    // Initialize DeepSeek client (legacy)
-    let client = match Entry::new("codemaster-app", "deepseek-api-key") {
-        Ok(entry) => match entry.get_password() {
-            Ok(key) => Some(DeepSeekClient::new(key)),
-            Err(_) => None,
-        },
-        Err(_) => None,
-    };
+    let current_provider = saved_provider();
 
-    // Initialize unified client based on saved provider
-    let unified_client = match &current_provider {
-        ModelProvider::DeepSeek => {
-            Entry::new("codemaster-app", "deepseek-api-key")
-                .ok()
-                .and_then(|e| e.get_password().ok())
-                .map(|key| UnifiedLLMClient::new(ModelConfig::deepseek(key)))
-        },
-        ModelProvider::Qwen => {
-            Entry::new("codemaster-app", "qwen-api-key")
-                .ok()
-                .and_then(|e| e.get_password().ok())
-                .map(|key| UnifiedLLMClient::new(ModelConfig::qwen(key)))
-        },
-    };
+    // Initialize the client for the saved provider
+    let unified_client = load_model_config(&current_provider)
+        .ok()
+        .map(UnifiedLLMClient::new);
 
     let mut registry = ToolRegistry::new();
     registry.register(ReadFileTool);
@@ -62,27 +36,37 @@ fn main() {
     registry.register(EditFileTool);
     registry.register(GrepTool);
     registry.register(GlobTool);
-    registry.register(BashTool);
+    let shells = Arc::new(ShellSessions::default());
+    registry.register(BashTool::new(shells.clone()));
     registry.register(ProjectStructureTool);
 
-    let app_state = AppState {
-        client: Mutex::new(client),
-        unified_client: Mutex::new(unified_client),
-        registry: Arc::new(registry),
-        current_provider: Mutex::new(current_provider),
-    };
+    let processes = Arc::new(ProcessTable::new());
+    registry.register(StartProcessTool::new(processes.clone()));
+    registry.register(ReadProcessOutputTool::new(processes.clone()));
+    registry.register(SendProcessInputTool::new(processes.clone()));
+    registry.register(KillProcessTool::new(processes.clone()));
 
     // Initialize database
     let database = match Database::new() {
-        Ok(db) => db,
+        Ok(db) => Arc::new(db),
         Err(e) => {
             eprintln!("Failed to initialize database: {}", e);
             std::process::exit(1);
         }
     };
+
+    let app_state = AppState {
+        unified_client: Mutex::new(unified_client),
+        registry: Arc::new(registry),
+        current_provider: Mutex::new(current_provider),
+        tasks: Mutex::new(HashMap::new()),
+        approvals: Arc::new(Approvals::new(database.clone())),
+        processes,
+        shells,
+    };
     
     let db_state = DbState {
-        db: Arc::new(database),
+        db: database,
     };
 
     tauri::Builder::default()
@@ -95,17 +79,31 @@ fn main() {
             commands::settings::test_connection,
             commands::settings::get_model_settings,
             commands::settings::set_model_settings,
+            commands::settings::get_generation_params,
+            commands::settings::set_generation_params,
             commands::settings::get_current_provider,
             commands::settings::test_model_connection,
+            commands::settings::list_models,
             commands::chat::send_message,
+            commands::chat::continue_task,
+            commands::chat::cancel_task,
+            commands::chat::respond_to_approval,
             commands::session::create_session,
             commands::session::list_sessions,
             commands::session::get_session,
             commands::session::update_session_title,
+            commands::session::set_session_workspace,
+            commands::session::get_session_params,
+            commands::session::get_session_usage,
+            commands::session::set_session_params,
+            commands::session::get_session_budget,
+            commands::session::set_session_budget,
             commands::session::delete_session,
             commands::session::get_session_messages,
             commands::session::save_message,
             commands::session::clear_session_messages,
+            commands::session::list_permission_rules,
+            commands::session::delete_permission_rule,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
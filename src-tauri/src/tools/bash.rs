@@ -1,11 +1,52 @@
-use crate::agent::registry::{Tool, ToolResult};
+use crate::agent::context::truncate_middle;
+use crate::agent::registry::{RiskLevel, Tool, ToolContext, ToolResult};
+use super::shell::{ShellSession, ShellSessions};
 use serde_json::{json, Value};
 use std::future::Future;
 use std::pin::Pin;
-use tokio::process::Command;
+use std::sync::Arc;
 use std::time::Duration;
 
-pub struct BashTool;
+// Output handed back to the model is capped; the terminal still sees everything
+const MAX_OUTPUT_CHARS: usize = 30_000;
+
+#[derive(Default)]
+pub struct BashTool {
+    shells: Arc<ShellSessions>,
+}
+
+impl BashTool {
+    pub fn new(shells: Arc<ShellSessions>) -> Self {
+        Self { shells }
+    }
+}
+
+/// Forgets the session's shell when dropped unless the command finished,
+/// so a call dropped mid-command (e.g. cancelled by the agent loop) does
+/// not leave a busy shell for the next call; dropping it kills the process.
+struct ForgetShell<'a> {
+    shells: &'a ShellSessions,
+    session_id: Option<&'a str>,
+    shell: &'a Arc<tokio::sync::Mutex<ShellSession>>,
+    finished: bool,
+}
+
+impl Drop for ForgetShell<'_> {
+    fn drop(&mut self) {
+        if let (false, Some(id)) = (self.finished, self.session_id) {
+            self.shells.remove_if_same(id, self.shell);
+        }
+    }
+}
+
+/// Quotes `s` as a single literal word for the session shell.
+fn quote(s: &str) -> String {
+    if cfg!(target_os = "windows") {
+        format!("'{}'", s.replace('\'', "''"))
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
 
 impl Tool for BashTool {
     fn name(&self) -> &str {
@@ -13,7 +54,7 @@ impl Tool for BashTool {
     }
 
     fn description(&self) -> &str {
-        "Execute a shell command. On Windows uses PowerShell."
+        "Execute a shell command. On Windows uses PowerShell. Commands of a session run in the same long-lived shell, so the working directory, environment variables and shell functions persist between calls."
     }
 
     fn parameters(&self) -> Value {
@@ -26,21 +67,29 @@ impl Tool for BashTool {
                 },
                 "workdir": {
                     "type": "string",
-                    "description": "Working directory"
+                    "description": "Directory to change into before running the command (the change persists)"
                 },
                 "timeout": {
                     "type": "integer",
-                    "description": "Timeout in seconds (default 120)"
+                    "description": "Timeout in seconds (default 120). A timed out command restarts the shell."
+                },
+                "reset_shell": {
+                    "type": "boolean",
+                    "description": "Start a fresh shell in the session's working directory before running the command"
                 }
             },
             "required": ["command"]
         })
     }
 
-    fn call(&self, args: Value) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
+    fn risk_level(&self) -> RiskLevel {
+        RiskLevel::Execute
+    }
+
+    fn call(&self, args: Value, ctx: ToolContext) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
+        let shells = self.shells.clone();
         Box::pin(async move {
             let command_str = args["command"].as_str().ok_or("Missing command parameter")?;
-            let workdir = args["workdir"].as_str();
             let timeout_secs = args["timeout"].as_u64().unwrap_or(120);
 
             let dangerous = ["rm -rf /", "format c:", "rd /s /q c:\\"];
@@ -50,44 +99,212 @@ impl Tool for BashTool {
                 }
             }
 
-            let mut cmd = if cfg!(target_os = "windows") {
-                let mut c = Command::new("powershell");
-                c.args(&["-Command", command_str]);
-                c
-            } else {
-                let mut c = Command::new("bash");
-                c.args(&["-c", command_str]);
-                c
+            let command = match args["workdir"].as_str() {
+                Some(dir) => {
+                    let dir = ctx.resolve_path(Some(dir))?;
+                    let cd = if cfg!(target_os = "windows") { "Set-Location -LiteralPath" } else { "cd" };
+                    format!("{} {}\n{}", cd, quote(&dir.to_string_lossy()), command_str)
+                }
+                None => command_str.to_string(),
+            };
+
+            let cwd = ctx.resolve_path(None)?;
+            // Without a session there is nothing to persist, so use a throwaway shell
+            let shell = match &ctx.session_id {
+                Some(id) => {
+                    if args["reset_shell"].as_bool().unwrap_or(false) {
+                        shells.remove(id);
+                    }
+                    shells.get_or_spawn(id, &cwd, &ctx.env).await?
+                }
+                None => Arc::new(tokio::sync::Mutex::new(ShellSession::spawn(&cwd, &ctx.env).await?)),
+            };
+
+            // On error the shell was killed; the next call starts a new one
+            let mut forget = ForgetShell {
+                shells: &shells,
+                session_id: ctx.session_id.as_deref(),
+                shell: &shell,
+                finished: false,
             };
+            let out = shell
+                .lock()
+                .await
+                .run(&command, Duration::from_secs(timeout_secs), &ctx.cancel, ctx.progress.as_ref())
+                .await?;
+            forget.finished = true;
 
-            if let Some(wd) = workdir {
-                cmd.current_dir(wd);
+            let output = truncate_middle(&out.output, MAX_OUTPUT_CHARS);
+            if out.exit_code == 0 {
+                Ok(output)
+            } else {
+                Ok(format!("Exit Code: {}\nOutput: {}", out.exit_code, output))
             }
+        })
+    }
+}
 
-            let child = cmd.stdout(std::process::Stdio::piped())
-                           .stderr(std::process::Stdio::piped())
-                           .spawn()
-                           .map_err(|e| format!("Failed to spawn command: {}", e))?;
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::agent::r#loop::AgentEvent;
+    use crate::agent::registry::ProgressSender;
+    use std::path::Path;
+    use std::time::Instant;
+    use tokio::sync::mpsc;
 
-            let output = match tokio::time::timeout(Duration::from_secs(timeout_secs), child.wait_with_output()).await {
-                Ok(result) => result.map_err(|e| format!("Failed to wait for command: {}", e))?,
-                Err(_) => return Err(format!("Command timed out after {} seconds", timeout_secs)),
-            };
+    #[tokio::test]
+    async fn runs_in_context_cwd_with_env() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut ctx = ToolContext {
+            cwd: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        ctx.env.insert("CODEMASTER_TEST".to_string(), "42".to_string());
+
+        let out = BashTool::default().call(json!({ "command": "pwd; echo $CODEMASTER_TEST" }), ctx).await.unwrap();
+
+        let canonical = std::fs::canonicalize(dir.path()).unwrap();
+        assert!(out.contains(&*canonical.to_string_lossy()), "{}", out);
+        assert!(out.contains("42"));
+    }
+
+    #[tokio::test]
+    async fn cancellation_kills_command() {
+        let ctx = ToolContext::default();
+        let cancel = ctx.cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            cancel.cancel();
+        });
 
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            let exit_code = output.status.code().unwrap_or(-1);
+        let started = Instant::now();
+        let result = BashTool::default().call(json!({ "command": "sleep 30" }), ctx).await;
 
-            if output.status.success() {
-                // If there's stderr even on success (warnings), append it
-                if !stderr.trim().is_empty() {
-                    Ok(format!("{}\nWARNINGS:\n{}", stdout, stderr))
-                } else {
-                    Ok(stdout.to_string())
+        assert_eq!(result.unwrap_err(), "Command cancelled by user");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn streams_output_lines_as_progress() {
+        let (tx, mut rx) = mpsc::channel(32);
+        let ctx = ToolContext {
+            progress: Some(ProgressSender::new("call_1".to_string(), tx)),
+            ..Default::default()
+        };
+
+        let out = BashTool::default().call(json!({ "command": "echo one; echo two" }), ctx).await.unwrap();
+        assert_eq!(out, "one\ntwo\n");
+
+        let mut chunks = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            match event {
+                AgentEvent::ToolProgress { id, chunk } => {
+                    assert_eq!(id, "call_1");
+                    chunks.push(chunk);
                 }
-            } else {
-                Ok(format!("Exit Code: {}\nStderr: {}\nStdout: {}", exit_code, stderr, stdout))
+                other => panic!("unexpected event {:?}", other),
             }
-        })
+        }
+        assert_eq!(chunks, vec!["one\n", "two\n"]);
+    }
+
+    fn session_ctx(dir: &Path) -> ToolContext {
+        ToolContext {
+            session_id: Some("s1".to_string()),
+            cwd: Some(dir.to_path_buf()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn shell_state_persists_within_session() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        let tool = BashTool::default();
+
+        tool.call(json!({ "command": "cd sub && export GREETING=hi && greet() { echo \"$GREETING there\"; }" }), session_ctx(dir.path()))
+            .await
+            .unwrap();
+        let out = tool.call(json!({ "command": "pwd; greet" }), session_ctx(dir.path())).await.unwrap();
+
+        assert!(out.contains("/sub\n"), "{}", out);
+        assert!(out.contains("hi there"), "{}", out);
+
+        let out = tool.call(json!({ "command": "pwd; echo \"[$GREETING]\"", "reset_shell": true }), session_ctx(dir.path()))
+            .await
+            .unwrap();
+        assert!(!out.contains("/sub\n"), "{}", out);
+        assert!(out.contains("[]"), "{}", out);
+    }
+
+    #[tokio::test]
+    async fn captures_exit_code_and_stderr() {
+        let dir = tempfile::tempdir().unwrap();
+        let tool = BashTool::default();
+
+        let out = tool.call(json!({ "command": "echo oops >&2; printf partial; (exit 3)" }), session_ctx(dir.path()))
+            .await
+            .unwrap();
+        assert_eq!(out, "Exit Code: 3\nOutput: oops\npartial");
+
+        // The shell survives a failing command
+        let out = tool.call(json!({ "command": "echo still here" }), session_ctx(dir.path())).await.unwrap();
+        assert_eq!(out, "still here\n");
+    }
+
+    #[tokio::test]
+    async fn timeout_restarts_shell() {
+        let dir = tempfile::tempdir().unwrap();
+        let tool = BashTool::default();
+
+        tool.call(json!({ "command": "export MARK=1" }), session_ctx(dir.path())).await.unwrap();
+        let err = tool.call(json!({ "command": "sleep 30", "timeout": 1 }), session_ctx(dir.path())).await.unwrap_err();
+        assert_eq!(err, "Command timed out after 1 seconds");
+
+        let out = tool.call(json!({ "command": "echo \"[$MARK]\"" }), session_ctx(dir.path())).await.unwrap();
+        assert_eq!(out, "[]\n");
+    }
+
+    #[tokio::test]
+    async fn commands_reading_stdin_get_no_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let tool = BashTool::default();
+
+        tool.call(json!({ "command": "MARK=1" }), session_ctx(dir.path())).await.unwrap();
+        let started = Instant::now();
+        for command in ["cat", "read x; echo \"[$x]\"", "bash", "sh -c 'cat; echo inner'"] {
+            tool.call(json!({ "command": command, "timeout": 10 }), session_ctx(dir.path())).await.unwrap();
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // Still the same top-level shell, not a nested one
+        let out = tool.call(json!({ "command": "echo \"[$MARK]\"" }), session_ctx(dir.path())).await.unwrap();
+        assert_eq!(out, "[1]\n");
+    }
+
+    #[tokio::test]
+    async fn dropped_call_kills_shell_and_its_children() {
+        let dir = tempfile::tempdir().unwrap();
+        let tool = BashTool::default();
+
+        tool.call(json!({ "command": "export MARK=1" }), session_ctx(dir.path())).await.unwrap();
+        // The agent loop drops the call future when the task is cancelled
+        let call = tool.call(json!({ "command": "(sleep 1; touch late) & sleep 30" }), session_ctx(dir.path()));
+        assert!(tokio::time::timeout(Duration::from_millis(300), call).await.is_err());
+
+        let out = tool.call(json!({ "command": "echo \"[$MARK]\"" }), session_ctx(dir.path())).await.unwrap();
+        assert_eq!(out, "[]\n");
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(!dir.path().join("late").exists());
+    }
+
+    #[test]
+    fn truncates_long_output() {
+        let long = "x".repeat(MAX_OUTPUT_CHARS + 100);
+        let out = truncate_middle(&long, MAX_OUTPUT_CHARS);
+        assert!(out.contains("[100 characters truncated]"));
+        assert!(out.len() < long.len());
+        assert_eq!(truncate_middle("short", MAX_OUTPUT_CHARS), "short");
     }
 }
@@ -1,9 +1,8 @@
-use crate::agent::registry::{Tool, ToolResult};
+use crate::agent::registry::{RiskLevel, Tool, ToolContext, ToolResult};
 use serde_json::{json, Value};
 use std::future::Future;
 use std::pin::Pin;
 use std::fs;
-use std::path::Path;
 
 pub struct ReadFileTool;
 
@@ -22,7 +21,7 @@ impl Tool for ReadFileTool {
             "properties": {
                 "path": {
                     "type": "string",
-                    "description": "The absolute path to the file"
+                    "description": "Path to the file, absolute or relative to the working directory"
                 },
                 "offset": {
                     "type": "integer",
@@ -37,18 +36,23 @@ impl Tool for ReadFileTool {
         })
     }
 
-    fn call(&self, args: Value) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
+    fn risk_level(&self) -> RiskLevel {
+        RiskLevel::ReadOnly
+    }
+
+    fn call(&self, args: Value, ctx: ToolContext) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
         Box::pin(async move {
             let path_str = args["path"].as_str().ok_or("Missing path parameter")?;
+            let path = ctx.resolve_path(Some(path_str))?;
             let offset = args["offset"].as_u64().unwrap_or(0);
             let limit = args["limit"].as_u64(); // None means read all
 
-            let metadata = fs::metadata(path_str).map_err(|e| format!("File not found or inaccessible: {}", e))?;
+            let metadata = fs::metadata(&path).map_err(|e| format!("File not found or inaccessible: {}", e))?;
             if metadata.len() > 5 * 1024 * 1024 {
                 return Err("File too large (>5MB). Use search or read specific lines.".to_string());
             }
 
-            let content = fs::read_to_string(path_str).map_err(|e| format!("Failed to read file: {}", e))?;
+            let content = fs::read_to_string(&path).map_err(|e| format!("Failed to read file: {}", e))?;
             
             let lines: Vec<&str> = content.lines().collect();
             let start = offset as usize;
@@ -92,7 +96,7 @@ impl Tool for WriteFileTool {
             "properties": {
                 "path": {
                     "type": "string",
-                    "description": "The absolute path to the file"
+                    "description": "Path to the file, absolute or relative to the working directory"
                 },
                 "content": {
                     "type": "string",
@@ -103,17 +107,21 @@ impl Tool for WriteFileTool {
         })
     }
 
-    fn call(&self, args: Value) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
+    fn risk_level(&self) -> RiskLevel {
+        RiskLevel::Write
+    }
+
+    fn call(&self, args: Value, ctx: ToolContext) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
         Box::pin(async move {
             let path_str = args["path"].as_str().ok_or("Missing path parameter")?;
             let content = args["content"].as_str().ok_or("Missing content parameter")?;
             
-            let path = Path::new(path_str);
+            let path = ctx.resolve_path(Some(path_str))?;
             if let Some(parent) = path.parent() {
                 fs::create_dir_all(parent).map_err(|e| format!("Failed to create parent directory: {}", e))?;
             }
             
-            fs::write(path, content).map_err(|e| format!("Failed to write file: {}", e))?;
+            fs::write(&path, content).map_err(|e| format!("Failed to write file: {}", e))?;
             
             Ok(format!("Successfully wrote to {}", path_str))
         })
@@ -137,7 +145,7 @@ impl Tool for EditFileTool {
             "properties": {
                 "path": {
                     "type": "string",
-                    "description": "The absolute path to the file"
+                    "description": "Path to the file, absolute or relative to the working directory"
                 },
                 "old_string": {
                     "type": "string",
@@ -152,13 +160,18 @@ impl Tool for EditFileTool {
         })
     }
 
-    fn call(&self, args: Value) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
+    fn risk_level(&self) -> RiskLevel {
+        RiskLevel::Write
+    }
+
+    fn call(&self, args: Value, ctx: ToolContext) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
         Box::pin(async move {
             let path_str = args["path"].as_str().ok_or("Missing path parameter")?;
             let old_string = args["old_string"].as_str().ok_or("Missing old_string parameter")?;
             let new_string = args["new_string"].as_str().ok_or("Missing new_string parameter")?;
+            let path = ctx.resolve_path(Some(path_str))?;
             
-            let content = fs::read_to_string(path_str).map_err(|e| format!("Failed to read file: {}", e))?;
+            let content = fs::read_to_string(&path).map_err(|e| format!("Failed to read file: {}", e))?;
             
             if !content.contains(old_string) {
                 return Err("old_string not found in file".to_string());
@@ -166,7 +179,7 @@ impl Tool for EditFileTool {
             
             let new_content = content.replacen(old_string, new_string, 1);
             
-            fs::write(path_str, new_content).map_err(|e| format!("Failed to write file: {}", e))?;
+            fs::write(&path, new_content).map_err(|e| format!("Failed to write file: {}", e))?;
             
             Ok(format!("Successfully edited {}", path_str))
         })
@@ -1,4 +1,7 @@
 pub mod file;
 pub mod search;
 pub mod bash;
+pub mod shell;
+pub mod process;
 pub mod project;
+pub mod sandbox;
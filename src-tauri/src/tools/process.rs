@@ -0,0 +1,571 @@
+// Background processes (dev servers, watchers) the agent starts and later inspects or stops
+use crate::agent::registry::{RiskLevel, Tool, ToolContext, ToolResult};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::{ChildStdin, Command};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+// Only the most recent output of each process is kept
+const OUTPUT_BUFFER_BYTES: usize = 64 * 1024;
+
+/// Output ring buffer plus exit status of one process.
+#[derive(Default)]
+struct OutputBuffer {
+    data: VecDeque<u8>,
+    // Absolute offset of `data[0]` in the process's total output
+    start: u64,
+    // Absolute offset up to which output has been returned to the agent
+    read_pos: u64,
+    // `Some(code)` once the process has exited; the code is `None` if it was killed
+    exit: Option<Option<i32>>,
+}
+
+impl OutputBuffer {
+    fn push(&mut self, bytes: &[u8]) {
+        self.data.extend(bytes);
+        if self.data.len() > OUTPUT_BUFFER_BYTES {
+            // Evict whole characters, so the buffer never starts mid-character
+            let mut excess = self.data.len() - OUTPUT_BUFFER_BYTES;
+            while self.data.get(excess).is_some_and(|b| b & 0xC0 == 0x80) {
+                excess += 1;
+            }
+            self.data.drain(..excess);
+            self.start += excess as u64;
+        }
+    }
+
+    /// Returns output not yet read and how many unread bytes were overwritten.
+    /// A character still missing bytes is left for the next read.
+    fn take_unread(&mut self) -> (String, u64) {
+        let from = self.read_pos.max(self.start);
+        let dropped = from - self.read_pos;
+        let mut bytes: Vec<u8> = self.data.iter().skip((from - self.start) as usize).copied().collect();
+        if self.exit.is_none() {
+            bytes.truncate(bytes.len() - incomplete_tail(&bytes));
+        }
+        self.read_pos = from + bytes.len() as u64;
+        (String::from_utf8_lossy(&bytes).into_owned(), dropped)
+    }
+}
+
+// Bytes at the end of `bytes` that start a UTF-8 character but do not finish it
+fn incomplete_tail(bytes: &[u8]) -> usize {
+    for back in 1..=bytes.len().min(3) {
+        let byte = bytes[bytes.len() - back];
+        if byte & 0xC0 != 0x80 {
+            let len = match byte {
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                0xF0..=0xF7 => 4,
+                _ => 1,
+            };
+            return if len > back { back } else { 0 };
+        }
+    }
+    0
+}
+
+#[derive(Default)]
+struct ProcessState {
+    buffer: Mutex<OutputBuffer>,
+    exited: Notify,
+}
+
+struct ManagedProcess {
+    command: String,
+    session_id: Option<String>,
+    state: Arc<ProcessState>,
+    stdin: Arc<tokio::sync::Mutex<Option<ChildStdin>>>,
+    kill: CancellationToken,
+}
+
+/// All background processes started by the agent, shared through `AppState`.
+#[derive(Default)]
+pub struct ProcessTable {
+    processes: Mutex<HashMap<String, ManagedProcess>>,
+    next_id: AtomicU64,
+}
+
+impl ProcessTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn start(&self, command: &str, cwd: &Path, ctx: &ToolContext) -> Result<String, String> {
+        let mut cmd = if cfg!(target_os = "windows") {
+            let mut c = Command::new("powershell");
+            c.args(["-Command", command]);
+            c
+        } else {
+            let mut c = Command::new("bash");
+            c.args(["-c", command]);
+            c
+        };
+        // Own process group, so killing it also stops the server's children
+        #[cfg(unix)]
+        cmd.process_group(0);
+
+        let mut child = cmd
+            .current_dir(cwd)
+            .envs(&ctx.env)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("Failed to start process: {}", e))?;
+
+        let state = Arc::new(ProcessState::default());
+        let stdin = child.stdin.take();
+        let stdout_reader = child.stdout.take().map(|out| tokio::spawn(collect_output(out, state.clone())));
+        let stderr_reader = child.stderr.take().map(|err| tokio::spawn(collect_output(err, state.clone())));
+        let kill = CancellationToken::new();
+
+        let waiter_state = state.clone();
+        let waiter_kill = kill.clone();
+        tokio::spawn(async move {
+            let code = tokio::select! {
+                status = child.wait() => status.ok().and_then(|s| s.code()),
+                _ = waiter_kill.cancelled() => {
+                    if let Some(pid) = child.id() {
+                        kill_tree(pid).await;
+                    }
+                    let _ = child.kill().await;
+                    None
+                },
+            };
+            // Give the readers a moment to pick up the last output
+            let _ = tokio::time::timeout(Duration::from_secs(1), async {
+                for reader in [stdout_reader, stderr_reader].into_iter().flatten() {
+                    let _ = reader.await;
+                }
+            }).await;
+            waiter_state.buffer.lock().unwrap().exit = Some(code);
+            waiter_state.exited.notify_waiters();
+        });
+
+        let id = format!("p{}", self.next_id.fetch_add(1, Ordering::Relaxed) + 1);
+        self.processes.lock().unwrap().insert(id.clone(), ManagedProcess {
+            command: command.to_string(),
+            session_id: ctx.session_id.clone(),
+            state,
+            stdin: Arc::new(tokio::sync::Mutex::new(stdin)),
+            kill,
+        });
+        Ok(id)
+    }
+
+    /// Looks up a process, hiding processes that belong to other sessions.
+    fn with_process<T>(&self, id: &str, ctx: &ToolContext, f: impl FnOnce(&ManagedProcess) -> T) -> Result<T, String> {
+        let processes = self.processes.lock().unwrap();
+        match processes.get(id) {
+            Some(p) if p.session_id == ctx.session_id => Ok(f(p)),
+            _ => Err(format!("No process with id {}", id)),
+        }
+    }
+
+    fn remove(&self, id: &str) {
+        self.processes.lock().unwrap().remove(id);
+    }
+
+    /// Reports on the process, forgetting it once it has exited: the report
+    /// holds the last of its output, so nothing is left to read.
+    async fn report(&self, id: &str, command: &str, state: &ProcessState, wait: Duration) -> String {
+        let (report, exited) = report(id, command, state, wait).await;
+        if exited {
+            self.remove(id);
+        }
+        report
+    }
+
+    /// Kills every process started in the session, e.g. when it is deleted.
+    pub fn kill_session(&self, session_id: &str) {
+        self.processes.lock().unwrap().retain(|_, p| {
+            let keep = p.session_id.as_deref() != Some(session_id);
+            if !keep {
+                p.kill.cancel();
+            }
+            keep
+        });
+    }
+}
+
+async fn collect_output<R: AsyncRead + Unpin>(mut pipe: R, state: Arc<ProcessState>) {
+    let mut buf = [0u8; 4096];
+    loop {
+        match pipe.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => state.buffer.lock().unwrap().push(&buf[..n]),
+        }
+    }
+}
+
+/// Kills `pid` and every process it started.
+pub(crate) async fn kill_tree(pid: u32) {
+    let mut cmd = if cfg!(target_os = "windows") {
+        let mut c = Command::new("taskkill");
+        c.args(["/T", "/F", "/PID", &pid.to_string()]);
+        c
+    } else {
+        let mut c = Command::new("kill");
+        c.args(["-KILL", "--", &format!("-{}", pid)]);
+        c
+    };
+    let _ = cmd.stdout(std::process::Stdio::null()).stderr(std::process::Stdio::null()).status().await;
+}
+
+/// Waits up to `wait` for the process to exit, then reports its status and
+/// the output produced since the last report and whether it has exited.
+async fn report(id: &str, command: &str, state: &ProcessState, wait: Duration) -> (String, bool) {
+    let exited = state.exited.notified();
+    tokio::pin!(exited);
+    exited.as_mut().enable();
+    if state.buffer.lock().unwrap().exit.is_none() {
+        let _ = tokio::time::timeout(wait, exited).await;
+    }
+
+    let mut buffer = state.buffer.lock().unwrap();
+    let (output, dropped) = buffer.take_unread();
+    let status = match buffer.exit {
+        None => "running".to_string(),
+        Some(Some(code)) => format!("exited with code {}", code),
+        Some(None) => "killed".to_string(),
+    };
+
+    let mut report = format!("Process {} (`{}`): {}\n", id, command, status);
+    if dropped > 0 {
+        report.push_str(&format!("[... {} bytes of earlier output were discarded ...]\n", dropped));
+    }
+    if output.is_empty() {
+        report.push_str("(no new output)");
+    } else {
+        report.push_str(&output);
+    }
+    (report, buffer.exit.is_some())
+}
+
+pub struct StartProcessTool {
+    table: Arc<ProcessTable>,
+}
+
+impl StartProcessTool {
+    pub fn new(table: Arc<ProcessTable>) -> Self {
+        Self { table }
+    }
+}
+
+impl Tool for StartProcessTool {
+    fn name(&self) -> &str {
+        "start_process"
+    }
+
+    fn description(&self) -> &str {
+        "Start a long-running command (dev server, watcher) in the background and return its id with the first output. Use read_process_output to check on it and kill_process to stop it."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "command": {
+                    "type": "string",
+                    "description": "Command to run"
+                },
+                "workdir": {
+                    "type": "string",
+                    "description": "Working directory (defaults to the session's working directory)"
+                },
+                "wait_ms": {
+                    "type": "integer",
+                    "description": "How long to wait for initial output in milliseconds (default 1000)"
+                }
+            },
+            "required": ["command"]
+        })
+    }
+
+    fn risk_level(&self) -> RiskLevel {
+        RiskLevel::Execute
+    }
+
+    fn call(&self, args: Value, ctx: ToolContext) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
+        let table = self.table.clone();
+        Box::pin(async move {
+            let command = args["command"].as_str().ok_or("Missing command parameter")?;
+            let workdir = ctx.resolve_path(args["workdir"].as_str())?;
+            let wait = Duration::from_millis(args["wait_ms"].as_u64().unwrap_or(1000));
+
+            let id = table.start(command, &workdir, &ctx)?;
+            let state = table.with_process(&id, &ctx, |p| p.state.clone())?;
+            Ok(table.report(&id, command, &state, wait).await)
+        })
+    }
+}
+
+pub struct ReadProcessOutputTool {
+    table: Arc<ProcessTable>,
+}
+
+impl ReadProcessOutputTool {
+    pub fn new(table: Arc<ProcessTable>) -> Self {
+        Self { table }
+    }
+}
+
+impl Tool for ReadProcessOutputTool {
+    fn name(&self) -> &str {
+        "read_process_output"
+    }
+
+    fn description(&self) -> &str {
+        "Get the status of a background process and the output it produced since the last read. Once a read reports that the process exited, its id is released."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "process_id": {
+                    "type": "string",
+                    "description": "Id returned by start_process"
+                },
+                "wait_ms": {
+                    "type": "integer",
+                    "description": "Wait up to this many milliseconds for the process to exit before reading (default 0)"
+                }
+            },
+            "required": ["process_id"]
+        })
+    }
+
+    fn risk_level(&self) -> RiskLevel {
+        RiskLevel::ReadOnly
+    }
+
+    fn call(&self, args: Value, ctx: ToolContext) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
+        let table = self.table.clone();
+        Box::pin(async move {
+            let id = args["process_id"].as_str().ok_or("Missing process_id parameter")?;
+            let wait = Duration::from_millis(args["wait_ms"].as_u64().unwrap_or(0));
+
+            let (command, state) = table.with_process(id, &ctx, |p| (p.command.clone(), p.state.clone()))?;
+            Ok(table.report(id, &command, &state, wait).await)
+        })
+    }
+}
+
+pub struct SendProcessInputTool {
+    table: Arc<ProcessTable>,
+}
+
+impl SendProcessInputTool {
+    pub fn new(table: Arc<ProcessTable>) -> Self {
+        Self { table }
+    }
+}
+
+impl Tool for SendProcessInputTool {
+    fn name(&self) -> &str {
+        "send_process_input"
+    }
+
+    fn description(&self) -> &str {
+        "Write text to the stdin of a background process and return the output that follows."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "process_id": {
+                    "type": "string",
+                    "description": "Id returned by start_process"
+                },
+                "input": {
+                    "type": "string",
+                    "description": "Text to send; include a trailing newline to submit a line"
+                },
+                "wait_ms": {
+                    "type": "integer",
+                    "description": "How long to wait for a response in milliseconds (default 500)"
+                }
+            },
+            "required": ["process_id", "input"]
+        })
+    }
+
+    fn risk_level(&self) -> RiskLevel {
+        RiskLevel::Execute
+    }
+
+    fn call(&self, args: Value, ctx: ToolContext) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
+        let table = self.table.clone();
+        Box::pin(async move {
+            let id = args["process_id"].as_str().ok_or("Missing process_id parameter")?;
+            let input = args["input"].as_str().ok_or("Missing input parameter")?;
+            let wait = Duration::from_millis(args["wait_ms"].as_u64().unwrap_or(500));
+
+            let (command, state, stdin) =
+                table.with_process(id, &ctx, |p| (p.command.clone(), p.state.clone(), p.stdin.clone()))?;
+            {
+                let mut stdin = stdin.lock().await;
+                let pipe = stdin.as_mut().ok_or("Process stdin is not available")?;
+                pipe.write_all(input.as_bytes()).await
+                    .and(pipe.flush().await)
+                    .map_err(|e| format!("Failed to write to process: {}", e))?;
+            }
+            Ok(table.report(id, &command, &state, wait).await)
+        })
+    }
+}
+
+pub struct KillProcessTool {
+    table: Arc<ProcessTable>,
+}
+
+impl KillProcessTool {
+    pub fn new(table: Arc<ProcessTable>) -> Self {
+        Self { table }
+    }
+}
+
+impl Tool for KillProcessTool {
+    fn name(&self) -> &str {
+        "kill_process"
+    }
+
+    fn description(&self) -> &str {
+        "Stop a background process (including its child processes) and return its remaining output."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "process_id": {
+                    "type": "string",
+                    "description": "Id returned by start_process"
+                }
+            },
+            "required": ["process_id"]
+        })
+    }
+
+    fn risk_level(&self) -> RiskLevel {
+        RiskLevel::Write
+    }
+
+    fn call(&self, args: Value, ctx: ToolContext) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
+        let table = self.table.clone();
+        Box::pin(async move {
+            let id = args["process_id"].as_str().ok_or("Missing process_id parameter")?;
+
+            let (command, state) = table.with_process(id, &ctx, |p| {
+                p.kill.cancel();
+                (p.command.clone(), p.state.clone())
+            })?;
+            let (report, _) = report(id, &command, &state, Duration::from_secs(5)).await;
+            table.remove(id);
+            Ok(report)
+        })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn tools() -> (StartProcessTool, ReadProcessOutputTool, SendProcessInputTool, KillProcessTool) {
+        let table = Arc::new(ProcessTable::new());
+        (
+            StartProcessTool::new(table.clone()),
+            ReadProcessOutputTool::new(table.clone()),
+            SendProcessInputTool::new(table.clone()),
+            KillProcessTool::new(table),
+        )
+    }
+
+    #[tokio::test]
+    async fn interacts_with_background_process() {
+        let (start, read, send, kill) = tools();
+
+        let out = start.call(json!({ "command": "echo ready; while read line; do echo \"got $line\"; done" }), ToolContext::default())
+            .await
+            .unwrap();
+        assert!(out.starts_with("Process p1 ("), "{}", out);
+        assert!(out.contains("running\nready\n"), "{}", out);
+
+        let out = send.call(json!({ "process_id": "p1", "input": "ping\n" }), ToolContext::default()).await.unwrap();
+        assert!(out.ends_with("got ping\n"), "{}", out);
+
+        let out = read.call(json!({ "process_id": "p1" }), ToolContext::default()).await.unwrap();
+        assert!(out.ends_with("(no new output)"), "{}", out);
+
+        let out = kill.call(json!({ "process_id": "p1" }), ToolContext::default()).await.unwrap();
+        assert!(out.contains("killed"), "{}", out);
+        assert!(read.call(json!({ "process_id": "p1" }), ToolContext::default()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn reports_exit_code() {
+        let (start, read, _, _) = tools();
+
+        start.call(json!({ "command": "sleep 0.5; echo done; exit 4", "wait_ms": 0 }), ToolContext::default()).await.unwrap();
+        let out = read.call(json!({ "process_id": "p1", "wait_ms": 5000 }), ToolContext::default()).await.unwrap();
+        assert!(out.contains("exited with code 4"), "{}", out);
+
+        // Its final output has been read, so the entry is gone
+        assert!(read.call(json!({ "process_id": "p1" }), ToolContext::default()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn hides_processes_of_other_sessions() {
+        let (start, read, _, _) = tools();
+        let ctx = ToolContext {
+            session_id: Some("a".to_string()),
+            ..Default::default()
+        };
+
+        start.call(json!({ "command": "sleep 30", "wait_ms": 0 }), ctx).await.unwrap();
+        assert!(read.call(json!({ "process_id": "p1" }), ToolContext::default()).await.is_err());
+    }
+
+    #[test]
+    fn ring_buffer_keeps_latest_output() {
+        let mut buffer = OutputBuffer::default();
+        buffer.push(&vec![b'a'; OUTPUT_BUFFER_BYTES]);
+        buffer.push(b"tail");
+
+        let (output, dropped) = buffer.take_unread();
+        assert_eq!(dropped, 4);
+        assert_eq!(output.len(), OUTPUT_BUFFER_BYTES);
+        assert!(output.ends_with("tail"));
+        assert_eq!(buffer.take_unread(), (String::new(), 0));
+    }
+
+    #[test]
+    fn keeps_cjk_characters_whole() {
+        let mut buffer = OutputBuffer::default();
+        let text = "编译完成".as_bytes();
+        // A read between the bytes of one character
+        buffer.push(&text[..4]);
+        assert_eq!(buffer.take_unread().0, "编");
+        buffer.push(&text[4..]);
+        assert_eq!(buffer.take_unread().0, "译完成");
+
+        // Eviction that would cut a character in half
+        let mut buffer = OutputBuffer::default();
+        buffer.push("中".repeat(OUTPUT_BUFFER_BYTES / 3 + 1).as_bytes());
+        let (output, _) = buffer.take_unread();
+        assert!(!output.contains('\u{FFFD}'));
+        assert!(output.starts_with('中'));
+    }
+}
@@ -1,9 +1,8 @@
-use crate::agent::registry::{Tool, ToolResult};
+use crate::agent::registry::{RiskLevel, Tool, ToolContext, ToolResult};
 use serde_json::{json, Value};
 use std::future::Future;
 use std::pin::Pin;
 use walkdir::WalkDir;
-use std::path::Path;
 
 pub struct ProjectStructureTool;
 
@@ -22,23 +21,25 @@ impl Tool for ProjectStructureTool {
             "properties": {
                 "path": {
                     "type": "string",
-                    "description": "Project root path"
+                    "description": "Project root path (defaults to the working directory)"
                 },
                 "depth": {
                     "type": "integer",
                     "description": "Max depth to traverse (default 2)"
                 }
-            },
-            "required": ["path"]
+            }
         })
     }
 
-    fn call(&self, args: Value) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
+    fn risk_level(&self) -> RiskLevel {
+        RiskLevel::ReadOnly
+    }
+
+    fn call(&self, args: Value, ctx: ToolContext) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
         Box::pin(async move {
-            let path_str = args["path"].as_str().ok_or("Missing path parameter")?;
+            let root = ctx.resolve_path(args["path"].as_str())?;
             let max_depth = args["depth"].as_u64().unwrap_or(2) as usize;
             
-            let root = Path::new(path_str);
             if !root.exists() {
                 return Err("Path does not exist".to_string());
             }
@@ -58,7 +59,7 @@ impl Tool for ProjectStructureTool {
             structure.push_str(&format!("Project Type: {}\n", project_type));
             structure.push_str("Structure:\n");
 
-            let walker = WalkDir::new(root).max_depth(max_depth).sort_by_file_name().into_iter();
+            let walker = WalkDir::new(&root).max_depth(max_depth).sort_by_file_name().into_iter();
             
             for entry in walker.filter_entry(|e| {
                 let name = e.file_name().to_string_lossy();
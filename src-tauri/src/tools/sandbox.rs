@@ -0,0 +1,132 @@
+// Workspace sandbox - keeps tool file access inside the session's project root
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Clone, Debug)]
+pub struct Workspace {
+    root: PathBuf,
+}
+
+impl Workspace {
+    pub fn new(root: impl AsRef<Path>) -> Result<Self, String> {
+        let root = root.as_ref();
+        let root = canonicalize(root)
+            .map_err(|e| format!("Invalid workspace root {}: {}", root.display(), e))?;
+        if !root.is_dir() {
+            return Err(format!("Workspace root is not a directory: {}", root.display()));
+        }
+        Ok(Self { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Resolves `path` (relative paths are taken from the root) to a canonical
+    /// absolute path, following symlinks, and rejects anything outside the root.
+    /// The path does not need to exist yet, so new files can be written.
+    pub fn resolve(&self, path: &str) -> Result<PathBuf, String> {
+        let joined = self.root.join(path);
+        let normalized = normalize(&joined);
+
+        // Canonicalize the deepest existing ancestor so symlinks are resolved,
+        // then re-append the part that does not exist yet.
+        let mut existing = normalized.as_path();
+        let mut rest = Vec::new();
+        let canonical = loop {
+            match canonicalize(existing) {
+                Ok(p) => break p,
+                Err(_) => {
+                    let Some(name) = existing.file_name() else {
+                        return Err(format!("Invalid path: {}", path));
+                    };
+                    rest.push(name.to_os_string());
+                    existing = existing.parent().ok_or_else(|| format!("Invalid path: {}", path))?;
+                }
+            }
+        };
+        let resolved = rest.iter().rev().fold(canonical, |acc, name| acc.join(name));
+
+        if !resolved.starts_with(&self.root) {
+            return Err(format!(
+                "Access denied: {} is outside the workspace root {}",
+                path,
+                self.root.display()
+            ));
+        }
+        Ok(resolved)
+    }
+}
+
+/// `fs::canonicalize` without the Windows `\\?\` verbatim prefix, which other
+/// tools (notably glob patterns, where `?` is a wildcard) do not understand.
+fn canonicalize(path: &Path) -> std::io::Result<PathBuf> {
+    let canonical = fs::canonicalize(path)?;
+    if cfg!(windows) {
+        let s = canonical.to_string_lossy();
+        if let Some(stripped) = s.strip_prefix(r"\\?\") {
+            if !stripped.starts_with("UNC") {
+                return Ok(PathBuf::from(stripped));
+            }
+        }
+    }
+    Ok(canonical)
+}
+
+/// Lexically removes `.` and `..` components.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            other => out.push(other.as_os_str()),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workspace() -> (tempfile::TempDir, Workspace) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("project/src")).unwrap();
+        fs::write(dir.path().join("project/src/main.rs"), "fn main() {}").unwrap();
+        fs::write(dir.path().join("secret.txt"), "secret").unwrap();
+        let ws = Workspace::new(dir.path().join("project")).unwrap();
+        (dir, ws)
+    }
+
+    #[test]
+    fn resolves_paths_inside_root() {
+        let (_dir, ws) = workspace();
+
+        assert_eq!(ws.resolve("src/main.rs").unwrap(), ws.root().join("src/main.rs"));
+        assert_eq!(ws.resolve("src/../src/./main.rs").unwrap(), ws.root().join("src/main.rs"));
+        // Files that do not exist yet are allowed, e.g. for write_file
+        assert_eq!(ws.resolve("src/new/lib.rs").unwrap(), ws.root().join("src/new/lib.rs"));
+    }
+
+    #[test]
+    fn rejects_paths_outside_root() {
+        let (dir, ws) = workspace();
+
+        assert!(ws.resolve("../secret.txt").unwrap_err().starts_with("Access denied"));
+        assert!(ws.resolve(dir.path().join("secret.txt").to_str().unwrap()).is_err());
+        assert!(ws.resolve("src/../../secret.txt").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_symlink_escape() {
+        let (dir, ws) = workspace();
+        std::os::unix::fs::symlink(dir.path(), ws.root().join("escape")).unwrap();
+
+        assert!(ws.resolve("escape/secret.txt").is_err());
+        assert!(ws.resolve("escape/new.txt").is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::agent::registry::{Tool, ToolResult};
+use crate::agent::registry::{RiskLevel, Tool, ToolContext, ToolResult};
 use serde_json::{json, Value};
 use std::future::Future;
 use std::pin::Pin;
@@ -27,7 +27,7 @@ impl Tool for GrepTool {
                 },
                 "path": {
                     "type": "string",
-                    "description": "Directory path to search in (defaults to current dir)"
+                    "description": "Directory path to search in (defaults to the working directory)"
                 },
                 "include": {
                     "type": "string",
@@ -38,10 +38,14 @@ impl Tool for GrepTool {
         })
     }
 
-    fn call(&self, args: Value) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
+    fn risk_level(&self) -> RiskLevel {
+        RiskLevel::ReadOnly
+    }
+
+    fn call(&self, args: Value, ctx: ToolContext) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
         Box::pin(async move {
             let pattern_str = args["pattern"].as_str().ok_or("Missing pattern parameter")?;
-            let path_str = args["path"].as_str().unwrap_or(".");
+            let root = ctx.resolve_path(args["path"].as_str())?;
             let include_pattern = args["include"].as_str();
 
             let regex = Regex::new(pattern_str).map_err(|e| format!("Invalid regex: {}", e))?;
@@ -49,7 +53,7 @@ impl Tool for GrepTool {
             let mut matches = Vec::new();
             let mut count = 0;
             
-            for entry in WalkDir::new(path_str).into_iter().filter_map(|e| e.ok()) {
+            for entry in WalkDir::new(&root).into_iter().filter_map(|e| e.ok()) {
                 if !entry.file_type().is_file() {
                     continue;
                 }
@@ -57,10 +61,11 @@ impl Tool for GrepTool {
                 let file_path = entry.path();
                 let path_string = file_path.to_string_lossy();
                 
-                // Skip hidden files/dirs and common ignore dirs
-                if path_string.contains("/.") || path_string.contains("\\.") || 
-                   path_string.contains("node_modules") || path_string.contains("target") || 
-                   path_string.contains(".git") {
+                // Skip hidden files/dirs and common ignore dirs (below the search root only)
+                let relative = file_path.strip_prefix(&root).unwrap_or(file_path).to_string_lossy();
+                if relative.starts_with('.') || relative.contains("/.") || relative.contains("\\.") || 
+                   relative.contains("node_modules") || relative.contains("target") || 
+                   relative.contains(".git") {
                     continue;
                 }
 
@@ -115,27 +120,35 @@ impl Tool for GlobTool {
             "properties": {
                 "pattern": {
                     "type": "string",
-                    "description": "Glob pattern (e.g., **/*.rs)"
+                    "description": "Glob pattern relative to the base path (e.g., **/*.rs)"
                 },
                 "path": {
                     "type": "string",
-                    "description": "Base path (optional)"
+                    "description": "Base path (defaults to the working directory)"
                 }
             },
             "required": ["pattern"]
         })
     }
 
-    fn call(&self, args: Value) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
+    fn risk_level(&self) -> RiskLevel {
+        RiskLevel::ReadOnly
+    }
+
+    fn call(&self, args: Value, ctx: ToolContext) -> Pin<Box<dyn Future<Output = ToolResult> + Send>> {
         Box::pin(async move {
             let pattern_str = args["pattern"].as_str().ok_or("Missing pattern parameter")?;
-            let base_path = args["path"].as_str().unwrap_or(".");
+            let base_path = ctx.resolve_path(args["path"].as_str())?;
+
+            // Keep the search under the base path; `path` selects where to look
+            let pattern_path = std::path::Path::new(pattern_str);
+            if pattern_path.is_absolute() || pattern_path.components().any(|c| c == std::path::Component::ParentDir) {
+                return Err("Pattern must be relative to the base path and must not contain '..'; use the path parameter instead".to_string());
+            }
             
-            let full_pattern = if base_path == "." {
-                pattern_str.to_string()
-            } else {
-                format!("{}/{}", base_path, pattern_str)
-            };
+            // Escape the base so brackets etc. in directory names are taken literally
+            let base = glob::Pattern::escape(&base_path.to_string_lossy());
+            let full_pattern = std::path::Path::new(&base).join(pattern_str).to_string_lossy().to_string();
 
             let mut files = Vec::new();
             let mut count = 0;
@@ -0,0 +1,220 @@
+// Long-lived shell processes, so `cd`, exported variables, activated virtualenvs
+// and shell functions survive between bash tool calls of the same session
+use crate::agent::registry::ProgressSender;
+use super::process::kill_tree;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+pub struct CommandOutput {
+    pub output: String,
+    pub exit_code: i32,
+}
+
+pub struct ShellSession {
+    child: Child,
+    // Cleared once the process tree has been killed
+    pid: Option<u32>,
+    stdin: ChildStdin,
+    // Lines from both stdout and stderr, in arrival order
+    lines: mpsc::UnboundedReceiver<String>,
+    sentinel: String,
+}
+
+impl ShellSession {
+    pub async fn spawn(cwd: &Path, env: &HashMap<String, String>) -> Result<Self, String> {
+        let mut cmd = if cfg!(target_os = "windows") {
+            let mut c = Command::new("powershell");
+            c.args(["-NoLogo", "-NoProfile", "-NonInteractive", "-Command", "-"]);
+            c
+        } else {
+            let mut c = Command::new("bash");
+            c.args(["--noprofile", "--norc"]);
+            c
+        };
+        // Own process group, so killing it also stops what the commands started
+        #[cfg(unix)]
+        cmd.process_group(0);
+
+        let mut child = cmd
+            .current_dir(cwd)
+            .envs(env)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("Failed to start shell: {}", e))?;
+
+        let stdin = child.stdin.take().ok_or("Failed to capture shell stdin")?;
+        let stdout = child.stdout.take().ok_or("Failed to capture shell stdout")?;
+        let stderr = child.stderr.take().ok_or("Failed to capture shell stderr")?;
+        let (tx, lines) = mpsc::unbounded_channel();
+        tokio::spawn(forward_lines(stdout, tx.clone()));
+        tokio::spawn(forward_lines(stderr, tx));
+
+        let mut session = Self {
+            pid: child.id(),
+            child,
+            stdin,
+            lines,
+            sentinel: format!("__CODEMASTER_DONE_{}__", uuid::Uuid::new_v4().simple()),
+        };
+        if !cfg!(target_os = "windows") {
+            // Merge stderr into stdout so output keeps its original interleaving
+            session.write("exec 2>&1\n").await?;
+        }
+        Ok(session)
+    }
+
+    /// Runs `command` and waits for its sentinel line. On error (timeout,
+    /// cancellation, shell exit) the shell is killed and must not be reused.
+    /// If the future is dropped before it finishes, the shell is in an
+    /// unknown state; drop the session too, which kills it.
+    pub async fn run(
+        &mut self,
+        command: &str,
+        timeout: Duration,
+        cancel: &CancellationToken,
+        progress: Option<&ProgressSender>,
+    ) -> Result<CommandOutput, String> {
+        // The shell reads its script from stdin, so the command gets empty
+        // input instead; otherwise `cat`, `read` or a nested shell would eat
+        // the sentinel line and the rest of the session's commands
+        let script = if cfg!(target_os = "windows") {
+            // Dot-sourcing keeps variables and functions in the session scope;
+            // the trailing blank line terminates the multi-line statement.
+            format!(
+                "$LASTEXITCODE = 0; $cmOk = $true\ntry {{ $null | . {{\n{}\n}} 2>&1 | Out-String -Stream }} catch {{ $_ | Out-String -Stream; $cmOk = $false }}\nWrite-Output \"{}$(if ($LASTEXITCODE) {{ $LASTEXITCODE }} elseif ($cmOk) {{ 0 }} else {{ 1 }})\"\n\n",
+                command, self.sentinel
+            )
+        } else {
+            // A group command still runs in the session shell, so `cd` and variables persist
+            format!("{{ {}\n}} </dev/null\nprintf '%s%d\\n' '{}' \"$?\"\n", command, self.sentinel)
+        };
+        self.write(&script).await?;
+
+        let mut output = String::new();
+        let deadline = tokio::time::sleep(timeout);
+        tokio::pin!(deadline);
+        loop {
+            let line = tokio::select! {
+                line = self.lines.recv() => line,
+                _ = &mut deadline => {
+                    self.kill().await;
+                    return Err(format!("Command timed out after {} seconds", timeout.as_secs()));
+                },
+                _ = cancel.cancelled() => {
+                    self.kill().await;
+                    return Err("Command cancelled by user".to_string());
+                },
+            };
+            let Some(line) = line else {
+                self.kill().await;
+                return Err(format!("Shell exited unexpectedly. Output:\n{}", output));
+            };
+
+            // Output without a trailing newline puts the sentinel mid-line
+            if let Some(pos) = line.find(&self.sentinel) {
+                let before = &line[..pos];
+                if !before.is_empty() {
+                    if let Some(progress) = progress {
+                        progress.chunk(before).await;
+                    }
+                    output.push_str(before);
+                }
+                let exit_code = line[pos + self.sentinel.len()..].trim().parse().unwrap_or(-1);
+                return Ok(CommandOutput { output, exit_code });
+            }
+
+            if let Some(progress) = progress {
+                progress.chunk(line.as_str()).await;
+            }
+            output.push_str(&line);
+        }
+    }
+
+    pub async fn kill(&mut self) {
+        if let Some(pid) = self.pid.take() {
+            kill_tree(pid).await;
+        }
+        let _ = self.child.kill().await;
+    }
+
+    async fn write(&mut self, script: &str) -> Result<(), String> {
+        self.stdin.write_all(script.as_bytes()).await
+            .and(self.stdin.flush().await)
+            .map_err(|e| format!("Failed to write to shell: {}", e))
+    }
+}
+
+impl Drop for ShellSession {
+    // kill_on_drop only reaches the shell itself, not the commands it started
+    fn drop(&mut self) {
+        if let (Some(pid), Ok(runtime)) = (self.pid.take(), tokio::runtime::Handle::try_current()) {
+            runtime.spawn(kill_tree(pid));
+        }
+    }
+}
+
+async fn forward_lines<R: AsyncRead + Unpin>(pipe: R, tx: mpsc::UnboundedSender<String>) {
+    let mut reader = BufReader::new(pipe);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                if tx.send(String::from_utf8_lossy(&line).into_owned()).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// One shell per agent session. A session's commands run one at a time.
+#[derive(Default)]
+pub struct ShellSessions {
+    sessions: Mutex<HashMap<String, Arc<tokio::sync::Mutex<ShellSession>>>>,
+}
+
+impl ShellSessions {
+    /// Returns the session's shell, starting it in `cwd` if there is none yet.
+    pub async fn get_or_spawn(
+        &self,
+        session_id: &str,
+        cwd: &Path,
+        env: &HashMap<String, String>,
+    ) -> Result<Arc<tokio::sync::Mutex<ShellSession>>, String> {
+        if let Some(shell) = self.sessions.lock().unwrap().get(session_id) {
+            return Ok(shell.clone());
+        }
+        let shell = Arc::new(tokio::sync::Mutex::new(ShellSession::spawn(cwd, env).await?));
+        Ok(self
+            .sessions
+            .lock()
+            .unwrap()
+            .entry(session_id.to_string())
+            .or_insert(shell)
+            .clone())
+    }
+
+    /// Drops the session's shell; the process is killed once no call uses it.
+    pub fn remove(&self, session_id: &str) {
+        self.sessions.lock().unwrap().remove(session_id);
+    }
+
+    /// Drops the session's shell if it is still `shell`, not one started since.
+    pub fn remove_if_same(&self, session_id: &str, shell: &Arc<tokio::sync::Mutex<ShellSession>>) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.get(session_id).is_some_and(|current| Arc::ptr_eq(current, shell)) {
+            sessions.remove(session_id);
+        }
+    }
+}
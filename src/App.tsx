@@ -8,7 +8,11 @@ import { ChatInput } from './components/Chat/ChatInput';
 import { MessageList } from './components/Chat/MessageList';
 import { Settings } from './components/Settings/Settings';
 import { SessionList } from './components/Session/SessionList';
-import { Message, AgentEvent } from './types';
+import { ApprovalPrompt, ApprovalChoice, commandPrefix } from './components/Chat/ApprovalPrompt';
+import { UsageSummary } from './components/Chat/UsageSummary';
+import { BudgetNotice } from './components/Chat/BudgetNotice';
+import { WorkspaceBar } from './components/Chat/WorkspaceBar';
+import { Message, AgentEvent, BudgetLimit } from './types';
 import { useChatStore } from './store/chatStore';
 import './App.css';
 
@@ -17,25 +21,42 @@ interface Session {
   title: string;
   created_at: number;
   updated_at: number;
+  workspace_root?: string | null;
 }
 
+// Project folder of the last session, offered for new ones
+const LAST_WORKSPACE_KEY = 'codemaster-last-workspace';
+
 function App() {
   const { t } = useTranslation();
   const [showSettings, setShowSettings] = useState(false);
+  // Limit that stopped the last task, offering to continue it
+  const [budgetLimit, setBudgetLimit] = useState<BudgetLimit | null>(null);
+  // Tools only run inside this folder; tasks are refused until one is set
+  const [workspaceRoot, setWorkspaceRoot] = useState(() => localStorage.getItem(LAST_WORKSPACE_KEY) || '');
+  const [workspaceError, setWorkspaceError] = useState<string | null>(null);
   
   const { 
     messages, 
     loading, 
     streamingContent, 
+    streamingReasoning,
     currentSessionId, 
+    pendingApproval,
     sessionRefreshTrigger,
+    usage,
     setMessages,
     addMessage,
     setLoading,
     setStreamingContent,
     appendStreamingContent,
+    setStreamingReasoning,
+    appendStreamingReasoning,
     setCurrentSessionId,
+    setCurrentTaskId,
+    setPendingApproval,
     triggerSessionRefresh,
+    addUsage,
     loadSession,
     resetSession
   } = useChatStore();
@@ -45,7 +66,8 @@ function App() {
     if (!currentSessionId && messages.length === 0) {
       setMessages([{ 
         role: 'assistant', 
-        content: t('welcome.description')
+        content: t('welcome.description'),
+        notice: true
       }]);
     }
   }, [t, currentSessionId, messages.length, setMessages]);
@@ -56,8 +78,13 @@ function App() {
       const payload = event.payload;
       console.log('Event received:', payload);
       
-      if (payload.type === 'Thinking') {
-          setStreamingContent('思考中...');
+      if (payload.type === 'TaskStarted') {
+          setCurrentTaskId(payload.content.task_id);
+      } else if (payload.type === 'Thinking') {
+          // Keep text already streamed when a request is retried or resumed
+          if (!useChatStore.getState().streamingContent) {
+             setStreamingContent('思考中...');
+          }
       } else if (payload.type === 'StreamChunk') {
           // If content is "Thinking...", replace it, otherwise append
           if (useChatStore.getState().streamingContent === '思考中...') {
@@ -65,16 +92,25 @@ function App() {
           } else {
              appendStreamingContent(payload.content as string);
           }
+      } else if (payload.type === 'Reasoning') {
+          if (useChatStore.getState().streamingContent === '思考中...') {
+             setStreamingContent('');
+          }
+          appendStreamingReasoning(payload.content as string);
       } else if (payload.type === 'StreamEnd') {
           setStreamingContent('');
+          setStreamingReasoning('');
       } else if (payload.type === 'NewMessage') {
-          const msg = payload.content as Message;
+          const { message, model } = payload.content;
+          const msg: Message = model ? { ...message, model } : message;
           addMessage(msg);
           // Save message to DB
           const sessionId = useChatStore.getState().currentSessionId;
           if (sessionId) {
             saveMessageToDb(sessionId, msg);
           }
+      } else if (payload.type === 'ApprovalRequired') {
+          setPendingApproval(payload.content);
       } else if (payload.type === 'ToolResult') {
            const content = payload.content as { name: string, result: string, id: string };
            const toolMsg: Message = {
@@ -88,13 +124,27 @@ function App() {
            if (sessionId) {
              saveMessageToDb(sessionId, toolMsg);
            }
+      } else if (payload.type === 'ProviderSwitched') {
+           const { from, to, reason } = payload.content;
+           addMessage({ role: 'assistant', content: `⚠️ ${t('chat.providerSwitched', { from, to, reason })}`, notice: true });
+      } else if (payload.type === 'BudgetExceeded') {
+           setBudgetLimit(payload.content);
+      } else if (payload.type === 'Usage') {
+           addUsage(payload.content.usage, payload.content.cost);
       } else if (payload.type === 'Error') {
-           addMessage({ role: 'assistant', content: `❌ Error: ${payload.content}` });
+           addMessage({ role: 'assistant', content: `❌ Error: ${payload.content}`, notice: true });
            setLoading(false);
            setStreamingContent('');
+           setStreamingReasoning('');
+      } else if (payload.type === 'Cancelled') {
+           setStreamingContent('');
+           setStreamingReasoning('');
+           setPendingApproval(null);
       } else if (payload.type === 'Done') {
            setLoading(false);
            setStreamingContent('');
+           setStreamingReasoning('');
+           setCurrentTaskId(null);
       }
     });
 
@@ -112,25 +162,45 @@ function App() {
         toolCalls: msg.tool_calls ? JSON.stringify(msg.tool_calls) : null,
         toolCallId: msg.tool_call_id || null,
         name: msg.name || null,
+        reasoningContent: msg.reasoning_content || null,
+        model: msg.model || null,
       });
     } catch (e) {
       console.error('Failed to save message:', e);
     }
   };
 
+  const handleApplyWorkspace = async (root: string) => {
+    try {
+      if (currentSessionId) {
+        await invoke('set_session_workspace', { id: currentSessionId, workspaceRoot: root });
+      }
+      setWorkspaceRoot(root);
+      setWorkspaceError(null);
+      localStorage.setItem(LAST_WORKSPACE_KEY, root);
+    } catch (e) {
+      setWorkspaceError(String(e));
+    }
+  };
+
   const handleSend = async (text: string) => {
     let sessionId = currentSessionId;
+    if (!workspaceRoot) {
+      setWorkspaceError(t('workspace.required'));
+      return;
+    }
     
     // Create session if needed
     if (!sessionId) {
       try {
         const title = text.slice(0, 30) + (text.length > 30 ? '...' : '');
-        const session = await invoke<Session>('create_session', { title });
+        const session = await invoke<Session>('create_session', { title, workspaceRoot });
         sessionId = session.id;
         setCurrentSessionId(sessionId);
         triggerSessionRefresh();
       } catch (e) {
         console.error('Failed to create session:', e);
+        setWorkspaceError(String(e));
         return;
       }
     }
@@ -138,25 +208,78 @@ function App() {
     const newMsg: Message = { role: 'user', content: text };
     addMessage(newMsg);
     setLoading(true);
+    setBudgetLimit(null);
 
     // Save user message
     await saveMessageToDb(sessionId, newMsg);
 
     try {
-      // Use current messages + new message for history
+      // Use current messages + new message for history, without the UI-only notices
       // Note: messages here is from closure, so it doesn't have newMsg yet
-      await invoke('send_message', { message: text, history: [...messages, newMsg] });
+      const history = [...messages, newMsg].filter(m => !m.notice);
+      await invoke('send_message', { message: text, history, sessionId });
     } catch (e) {
-      addMessage({ role: 'assistant', content: `Error sending message: ${e}` });
+      addMessage({ role: 'assistant', content: `Error sending message: ${e}`, notice: true });
       setLoading(false);
     }
   };
 
+  const handleContinue = async () => {
+    if (!currentSessionId) return;
+    setBudgetLimit(null);
+    setLoading(true);
+    try {
+      await invoke('continue_task', { sessionId: currentSessionId });
+    } catch (e) {
+      addMessage({ role: 'assistant', content: `Error continuing task: ${e}`, notice: true });
+      setLoading(false);
+    }
+  };
+
+  const handleStop = async () => {
+    const taskId = useChatStore.getState().currentTaskId;
+    if (!taskId) return;
+    try {
+      await invoke('cancel_task', { taskId });
+    } catch (e) {
+      console.error('Failed to cancel task:', e);
+    }
+  };
+
+  const handleApproval = async (choice: ApprovalChoice) => {
+    if (!pendingApproval) return;
+    const prefix = commandPrefix(pendingApproval.args);
+    const remember =
+      choice === 'tool' ? { kind: 'Tool' } :
+      choice === 'prefix' && prefix ? { kind: 'CommandPrefix', prefix } :
+      null;
+    try {
+      await invoke('respond_to_approval', {
+        requestId: pendingApproval.request_id,
+        response: { approved: choice !== 'deny', remember },
+      });
+    } catch (e) {
+      console.error('Failed to respond to approval:', e);
+    }
+    setPendingApproval(null);
+  };
+
   const handleSelectSession = async (id: string) => {
+    setBudgetLimit(null);
+    setWorkspaceError(null);
     await loadSession(id);
+    try {
+      const session = await invoke<Session | null>('get_session', { id });
+      setWorkspaceRoot(session?.workspace_root || '');
+    } catch (e) {
+      console.error('Failed to load session workspace:', e);
+    }
   };
 
   const handleNewSession = () => {
+    setBudgetLimit(null);
+    setWorkspaceError(null);
+    setWorkspaceRoot(localStorage.getItem(LAST_WORKSPACE_KEY) || '');
     resetSession();
     // Welcome message will be added by the useEffect
   };
@@ -178,8 +301,12 @@ function App() {
         onSettingsClick={() => setShowSettings(true)}
         content={
           <>
-            <MessageList messages={messages} streamingContent={streamingContent} />
-            <ChatInput onSend={handleSend} disabled={loading} />
+            <WorkspaceBar root={workspaceRoot} error={workspaceError} onApply={handleApplyWorkspace} />
+            <MessageList messages={messages} streamingContent={streamingContent} streamingReasoning={streamingReasoning} />
+            {pendingApproval && <ApprovalPrompt approval={pendingApproval} onRespond={handleApproval} />}
+            {budgetLimit && !loading && <BudgetNotice limit={budgetLimit} onContinue={handleContinue} />}
+            {usage && usage.requests > 0 && <UsageSummary usage={usage} />}
+            <ChatInput onSend={handleSend} onStop={handleStop} disabled={loading} />
           </>
         }
       />
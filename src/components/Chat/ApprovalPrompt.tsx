@@ -0,0 +1,48 @@
+import { useTranslation } from 'react-i18next';
+import { PendingApproval } from '../../types';
+import './ApprovalPrompt.css';
+
+export type ApprovalChoice = 'deny' | 'once' | 'tool' | 'prefix';
+
+interface ApprovalPromptProps {
+  approval: PendingApproval;
+  onRespond: (choice: ApprovalChoice) => void;
+}
+
+// First word of a shell command, used for "always allow this command prefix"
+export function commandPrefix(args: string): string | null {
+  try {
+    const command = JSON.parse(args).command;
+    if (typeof command !== 'string') return null;
+    const first = command.trim().split(/\s+/)[0];
+    return first ? `${first} ` : null;
+  } catch {
+    return null;
+  }
+}
+
+export function ApprovalPrompt({ approval, onRespond }: ApprovalPromptProps) {
+  const { t } = useTranslation();
+  const prefix = commandPrefix(approval.args);
+
+  return (
+    <div className="approval-prompt">
+      <div className="approval-header">
+        <span className="tool-icon">⚠️</span>
+        <span>{t('approval.title')}</span>
+        <span className="tool-name">{approval.name}</span>
+      </div>
+      <pre className="tool-code">{approval.args}</pre>
+      <div className="approval-actions">
+        <button onClick={() => onRespond('once')}>{t('approval.allowOnce')}</button>
+        <button onClick={() => onRespond('tool')}>{t('approval.alwaysTool')}</button>
+        {prefix && (
+          <button onClick={() => onRespond('prefix')}>
+            {t('approval.alwaysPrefix', { prefix: prefix.trim() })}
+          </button>
+        )}
+        <button className="deny" onClick={() => onRespond('deny')}>{t('approval.deny')}</button>
+      </div>
+    </div>
+  );
+}
@@ -0,0 +1,18 @@
+import { useTranslation } from 'react-i18next';
+import { BudgetLimit } from '../../types';
+import './BudgetNotice.css';
+
+interface BudgetNoticeProps {
+  limit: BudgetLimit;
+  onContinue: () => void;
+}
+
+export function BudgetNotice({ limit, onContinue }: BudgetNoticeProps) {
+  const { t } = useTranslation();
+  return (
+    <div className="budget-notice">
+      <span>{t(`budget.${limit}`)}</span>
+      <button onClick={onContinue}>{t('budget.continue')}</button>
+    </div>
+  );
+}
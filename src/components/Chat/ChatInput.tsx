@@ -4,10 +4,11 @@ import './ChatInput.css';
 
 interface ChatInputProps {
   onSend: (message: string) => void;
+  onStop?: () => void;
   disabled?: boolean;
 }
 
-export function ChatInput({ onSend, disabled }: ChatInputProps) {
+export function ChatInput({ onSend, onStop, disabled }: ChatInputProps) {
   const { t } = useTranslation();
   const [input, setInput] = useState('');
 
@@ -36,9 +37,15 @@ export function ChatInput({ onSend, disabled }: ChatInputProps) {
         disabled={disabled}
         rows={3}
       />
-      <button className="chat-send-btn" onClick={handleSend} disabled={disabled || !input.trim()}>
-        {t('chat.send')}
-      </button>
+      {disabled && onStop ? (
+        <button className="chat-send-btn" onClick={onStop}>
+          {t('chat.stop')}
+        </button>
+      ) : (
+        <button className="chat-send-btn" onClick={handleSend} disabled={disabled || !input.trim()}>
+          {t('chat.send')}
+        </button>
+      )}
     </div>
   );
 }
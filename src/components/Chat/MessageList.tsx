@@ -1,4 +1,5 @@
 import { useEffect, useRef } from 'react';
+import { useTranslation } from 'react-i18next';
 import Markdown from 'react-markdown';
 import remarkGfm from 'remark-gfm';
 import { Prism as SyntaxHighlighter } from 'react-syntax-highlighter';
@@ -10,6 +11,7 @@ import './MessageList.css';
 interface MessageListProps {
   messages: Message[];
   streamingContent?: string;
+  streamingReasoning?: string;
 }
 
 const MarkdownComponents = {
@@ -33,16 +35,28 @@ const MarkdownComponents = {
   }
 };
 
-export function MessageList({ messages, streamingContent }: MessageListProps) {
+function Reasoning({ content, open }: { content: string; open?: boolean }) {
+  const { t } = useTranslation();
+  return (
+    <details className="reasoning" open={open}>
+      <summary>{t('chat.reasoning')}</summary>
+      <div className="reasoning-text">{content}</div>
+    </details>
+  );
+}
+
+export function MessageList({ messages, streamingContent, streamingReasoning }: MessageListProps) {
   const bottomRef = useRef<HTMLDivElement>(null);
 
   useEffect(() => {
     bottomRef.current?.scrollIntoView({ behavior: 'smooth' });
-  }, [messages, streamingContent]);
+  }, [messages, streamingContent, streamingReasoning]);
 
   return (
     <div className="message-list">
-      {messages.map((msg, idx) => (
+      {messages.map((msg, idx) => msg.notice ? (
+        <div key={idx} className="message-notice">{msg.content}</div>
+      ) : (
         <div key={idx} className={`message ${msg.role}`}>
           <div className="message-avatar">
             {msg.role === 'user' ? '👤' : msg.role === 'assistant' ? '🤖' : '⚙️'}
@@ -52,6 +66,7 @@ export function MessageList({ messages, streamingContent }: MessageListProps) {
             
             {msg.role === 'assistant' && (
               <>
+                {msg.reasoning_content && <Reasoning content={msg.reasoning_content} />}
                 {msg.content && (
                    <div className="markdown-body">
                      <Markdown 
@@ -83,10 +98,11 @@ export function MessageList({ messages, streamingContent }: MessageListProps) {
         </div>
       ))}
       
-      {streamingContent && (
+      {(streamingContent || streamingReasoning) && (
         <div className="message assistant streaming">
            <div className="message-avatar">🤖</div>
            <div className="message-body">
+             {streamingReasoning && <Reasoning content={streamingReasoning} open />}
              <div className="markdown-body">
                <Markdown 
                  remarkPlugins={[remarkGfm]}
@@ -0,0 +1,21 @@
+import { useTranslation } from 'react-i18next';
+import { SessionUsage } from '../../types';
+import './UsageSummary.css';
+
+interface UsageSummaryProps {
+  usage: SessionUsage;
+}
+
+const formatTokens = (n: number) => (n >= 1000 ? `${(n / 1000).toFixed(1)}K` : `${n}`);
+
+export function UsageSummary({ usage }: UsageSummaryProps) {
+  const { t } = useTranslation();
+  return (
+    <div className="usage-summary">
+      <span>{t('usage.input')}: {formatTokens(usage.prompt_tokens)}</span>
+      {usage.cached_tokens > 0 && <span>{t('usage.cached')}: {formatTokens(usage.cached_tokens)}</span>}
+      <span>{t('usage.output')}: {formatTokens(usage.completion_tokens)}</span>
+      <span>{t('usage.cost')}: ¥{usage.cost.toFixed(4)}</span>
+    </div>
+  );
+}
@@ -0,0 +1,34 @@
+import { useState, useEffect } from 'react';
+import { useTranslation } from 'react-i18next';
+import './WorkspaceBar.css';
+
+interface WorkspaceBarProps {
+  root: string;
+  error: string | null;
+  onApply: (root: string) => void;
+}
+
+// Project folder the agent's tools are confined to
+export function WorkspaceBar({ root, error, onApply }: WorkspaceBarProps) {
+  const { t } = useTranslation();
+  const [draft, setDraft] = useState(root);
+
+  useEffect(() => setDraft(root), [root]);
+
+  return (
+    <div className="workspace-bar">
+      <label>{t('workspace.label')}</label>
+      <input
+        type="text"
+        value={draft}
+        onChange={(e) => setDraft(e.target.value)}
+        onKeyDown={(e) => e.key === 'Enter' && onApply(draft.trim())}
+        placeholder={t('workspace.placeholder')}
+      />
+      <button onClick={() => onApply(draft.trim())} disabled={!draft.trim() || draft.trim() === root}>
+        {t('workspace.apply')}
+      </button>
+      {error && <span className="workspace-error">{error}</span>}
+    </div>
+  );
+}
@@ -8,6 +8,7 @@ interface Session {
   title: string;
   created_at: number;
   updated_at: number;
+  workspace_root?: string | null;
 }
 
 interface SessionListProps {
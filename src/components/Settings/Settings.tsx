@@ -7,17 +7,111 @@ interface SettingsProps {
   onClose: () => void;
 }
 
+interface CustomEndpoint {
+  base_url: string;
+  model_name: string;
+  extra_headers: Record<string, string>;
+  stream_usage: boolean;
+}
+
 interface ModelSettings {
   provider: string;
-  deepseek_key: string | null;
-  qwen_key: string | null;
+  api_keys: Record<string, string>;
+  models: Record<string, string>;
+  custom_endpoint: CustomEndpoint | null;
+  context_budgets: Record<string, number>;
+  fallbacks: Fallback[];
+}
+
+// Provider to continue on when the selected one keeps failing
+interface Fallback {
+  provider: string;
+  model: string | null;
+}
+
+interface ModelInfo {
+  id: string;
+  context_window: number | null;
+  input_price: number | null;
+  output_price: number | null;
+}
+
+interface GenerationParams {
+  temperature?: number | null;
+  top_p?: number | null;
+  max_tokens?: number | null;
+  stop?: string[] | null;
+  seed?: number | null;
+  presence_penalty?: number | null;
+  frequency_penalty?: number | null;
+  response_format?: { type: 'text' | 'json_object' } | null;
+}
+
+type NumericParam = 'temperature' | 'top_p' | 'max_tokens' | 'seed' | 'presence_penalty' | 'frequency_penalty';
+
+const NUMERIC_PARAMS: { key: NumericParam; label: string; step: number; placeholder: string }[] = [
+  { key: 'temperature', label: 'Temperature', step: 0.1, placeholder: '0 - 2' },
+  { key: 'top_p', label: 'Top P', step: 0.05, placeholder: '0 - 1' },
+  { key: 'max_tokens', label: 'Max Tokens', step: 1, placeholder: '默认' },
+  { key: 'seed', label: 'Seed', step: 1, placeholder: '随机' },
+  { key: 'presence_penalty', label: 'Presence Penalty', step: 0.1, placeholder: '-2 - 2' },
+  { key: 'frequency_penalty', label: 'Frequency Penalty', step: 0.1, placeholder: '-2 - 2' },
+];
+
+const describeModel = (m: ModelInfo) => {
+  const parts: string[] = [];
+  if (m.context_window) parts.push(`${Math.round(m.context_window / 1000)}K`);
+  if (m.input_price != null && m.output_price != null) parts.push(`¥${m.input_price}/¥${m.output_price} 每百万 tokens`);
+  return parts.length ? `${m.id} (${parts.join(', ')})` : m.id;
+};
+
+interface ProviderOption {
+  id: string;
+  name: string;
+  desc: string;
+  icon: string;
+  keyUrl?: string;
 }
 
+const PROVIDERS: ProviderOption[] = [
+  { id: 'deepseek', name: 'DeepSeek', desc: '推荐', icon: '🔮', keyUrl: 'https://platform.deepseek.com/api_keys' },
+  { id: 'qwen', name: '通义千问', desc: 'Qwen', icon: '🌐', keyUrl: 'https://dashscope.console.aliyun.com/apiKey' },
+  { id: 'moonshot', name: 'Kimi', desc: 'Moonshot', icon: '🌙', keyUrl: 'https://platform.moonshot.cn/console/api-keys' },
+  { id: 'zhipu', name: '智谱 GLM', desc: 'Zhipu', icon: '🧠', keyUrl: 'https://open.bigmodel.cn/usercenter/apikeys' },
+  { id: 'doubao', name: '豆包', desc: 'Doubao', icon: '🫘', keyUrl: 'https://console.volcengine.com/ark/region:ark+cn-beijing/apiKey' },
+  { id: 'baichuan', name: '百川', desc: 'Baichuan', icon: '🏔️', keyUrl: 'https://platform.baichuan-ai.com/console/apikey' },
+  { id: 'minimax', name: 'MiniMax', desc: '海螺', icon: '🐚', keyUrl: 'https://platform.minimaxi.com/user-center/basic-information/interface-key' },
+  { id: 'custom', name: '自定义', desc: 'OpenAI 兼容', icon: '🛠️' },
+];
+
+// Headers are edited as one "Name: value" pair per line
+const parseHeaders = (text: string): Record<string, string> => {
+  const headers: Record<string, string> = {};
+  text.split('\n').forEach(line => {
+    const idx = line.indexOf(':');
+    if (idx > 0) {
+      headers[line.slice(0, idx).trim()] = line.slice(idx + 1).trim();
+    }
+  });
+  return headers;
+};
+
+const formatHeaders = (headers: Record<string, string>) =>
+  Object.entries(headers).map(([name, value]) => `${name}: ${value}`).join('\n');
+
 export function Settings({ onClose }: SettingsProps) {
   const { t } = useTranslation();
   const [provider, setProvider] = useState('deepseek');
-  const [deepseekKey, setDeepseekKey] = useState('');
-  const [qwenKey, setQwenKey] = useState('');
+  const [apiKeys, setApiKeys] = useState<Record<string, string>>({});
+  const [models, setModels] = useState<Record<string, string>>({});
+  const [availableModels, setAvailableModels] = useState<ModelInfo[]>([]);
+  const [customBaseUrl, setCustomBaseUrl] = useState('');
+  const [customModel, setCustomModel] = useState('');
+  const [customHeaders, setCustomHeaders] = useState('');
+  const [customStreamUsage, setCustomStreamUsage] = useState(false);
+  const [params, setParams] = useState<GenerationParams>({});
+  const [contextBudgets, setContextBudgets] = useState<Record<string, number>>({});
+  const [fallbacks, setFallbacks] = useState<Fallback[]>([]);
   const [loading, setLoading] = useState(false);
   const [testResult, setTestResult] = useState('');
   const [activeTab, setActiveTab] = useState<'model' | 'general'>('model');
@@ -26,25 +120,73 @@ export function Settings({ onClose }: SettingsProps) {
     loadSettings();
   }, []);
 
+  useEffect(() => {
+    if (provider === 'custom') {
+      setAvailableModels([]);
+      return;
+    }
+    invoke<ModelInfo[]>('list_models', { provider, apiKey: apiKeys[provider] || null })
+      .then(setAvailableModels)
+      .catch(e => {
+        console.error('Failed to list models:', e);
+        setAvailableModels([]);
+      });
+  }, [provider, apiKeys[provider]]);
+
   const loadSettings = async () => {
     try {
+      setParams(await invoke<GenerationParams>('get_generation_params'));
       const settings = await invoke<ModelSettings>('get_model_settings');
       setProvider(settings.provider || 'deepseek');
-      if (settings.deepseek_key) setDeepseekKey(settings.deepseek_key);
-      if (settings.qwen_key) setQwenKey(settings.qwen_key);
+      setApiKeys(settings.api_keys || {});
+      setModels(settings.models || {});
+      setContextBudgets(settings.context_budgets || {});
+      setFallbacks(settings.fallbacks || []);
+      if (settings.custom_endpoint) {
+        setCustomBaseUrl(settings.custom_endpoint.base_url);
+        setCustomModel(settings.custom_endpoint.model_name);
+        setCustomHeaders(formatHeaders(settings.custom_endpoint.extra_headers));
+        setCustomStreamUsage(settings.custom_endpoint.stream_usage);
+      }
     } catch (e) {
       console.error('Failed to load settings:', e);
     }
   };
 
+  const currentProvider = PROVIDERS.find(p => p.id === provider) || PROVIDERS[0];
+  const selectedModel = provider === 'custom' ? customModel : models[provider] || availableModels[0]?.id || '';
+  const catalogWindow = availableModels.find(m => m.id === selectedModel)?.context_window;
+
+  const setContextBudget = (value: string) => {
+    const budgets = { ...contextBudgets };
+    if (value === '') {
+      delete budgets[selectedModel];
+    } else {
+      budgets[selectedModel] = Number(value);
+    }
+    setContextBudgets(budgets);
+  };
+
+  const updateFallback = (index: number, fallback: Fallback) =>
+    setFallbacks(fallbacks.map((f, i) => (i === index ? fallback : f)));
+
+  const customEndpoint = (): CustomEndpoint | null =>
+    customBaseUrl || customModel
+      ? { base_url: customBaseUrl, model_name: customModel, extra_headers: parseHeaders(customHeaders), stream_usage: customStreamUsage }
+      : null;
+
   const handleSave = async () => {
     setLoading(true);
     try {
       await invoke('set_model_settings', {
         provider,
-        deepseekKey: deepseekKey || null,
-        qwenKey: qwenKey || null,
+        apiKeys,
+        models,
+        customEndpoint: customEndpoint(),
+        contextBudgets,
+        fallbacks,
       });
+      await invoke('set_generation_params', { params });
       setTestResult('✅ 设置已保存');
     } catch (e) {
       setTestResult(`❌ 保存失败: ${e}`);
@@ -54,13 +196,18 @@ export function Settings({ onClose }: SettingsProps) {
   };
 
   const handleTest = async (testProvider: string, apiKey: string) => {
-    if (!apiKey) {
+    if (!apiKey && testProvider !== 'custom') {
       setTestResult('请先输入 API Key');
       return;
     }
     setLoading(true);
     try {
-      const res = await invoke<string>('test_model_connection', { provider: testProvider, apiKey });
+      const res = await invoke<string>('test_model_connection', {
+        provider: testProvider,
+        apiKey: apiKey || null,
+        model: models[testProvider] || null,
+        customEndpoint: testProvider === 'custom' ? customEndpoint() : null,
+      });
       setTestResult(`✅ ${res}`);
     } catch (e) {
       setTestResult(`❌ 连接失败: ${e}`);
@@ -98,67 +245,167 @@ export function Settings({ onClose }: SettingsProps) {
               <div className="form-group">
                 <label>选择模型</label>
                 <div className="model-selector">
-                  <button 
-                    className={`model-option ${provider === 'deepseek' ? 'selected' : ''}`}
-                    onClick={() => setProvider('deepseek')}
-                  >
-                    <span className="model-icon">🔮</span>
-                    <span className="model-name">DeepSeek</span>
-                    <span className="model-desc">推荐</span>
-                  </button>
-                  <button 
-                    className={`model-option ${provider === 'qwen' ? 'selected' : ''}`}
-                    onClick={() => setProvider('qwen')}
-                  >
-                    <span className="model-icon">🌐</span>
-                    <span className="model-name">通义千问</span>
-                    <span className="model-desc">Qwen</span>
-                  </button>
+                  {PROVIDERS.map(p => (
+                    <button 
+                      key={p.id}
+                      className={`model-option ${provider === p.id ? 'selected' : ''}`}
+                      onClick={() => setProvider(p.id)}
+                    >
+                      <span className="model-icon">{p.icon}</span>
+                      <span className="model-name">{p.name}</span>
+                      <span className="model-desc">{p.desc}</span>
+                    </button>
+                  ))}
                 </div>
               </div>
 
+              {provider !== 'custom' && availableModels.length > 0 && (
+                <div className="form-group">
+                  <label>模型</label>
+                  <select 
+                    value={models[provider] || availableModels[0].id} 
+                    onChange={(e) => setModels({ ...models, [provider]: e.target.value })}
+                  >
+                    {availableModels.map(m => (
+                      <option key={m.id} value={m.id}>{describeModel(m)}</option>
+                    ))}
+                  </select>
+                </div>
+              )}
+
+              {provider === 'custom' && (
+                <div className="form-group">
+                  <label>自定义端点 (vLLM / Ollama / 内网网关)</label>
+                  <input 
+                    type="text" 
+                    value={customBaseUrl} 
+                    onChange={(e) => setCustomBaseUrl(e.target.value)} 
+                    placeholder="http://localhost:11434/v1"
+                  />
+                  <input 
+                    type="text" 
+                    value={customModel} 
+                    onChange={(e) => setCustomModel(e.target.value)} 
+                    placeholder="模型名称，如 qwen2.5-coder:32b"
+                  />
+                  <textarea 
+                    value={customHeaders} 
+                    onChange={(e) => setCustomHeaders(e.target.value)} 
+                    placeholder={'额外请求头，每行一个\nX-Api-Team: infra'}
+                    rows={3}
+                  />
+                  <label className="checkbox-row">
+                    <input 
+                      type="checkbox" 
+                      checked={customStreamUsage} 
+                      onChange={(e) => setCustomStreamUsage(e.target.checked)} 
+                    />
+                    流式返回用量（需端点支持 stream_options）
+                  </label>
+                </div>
+              )}
+
               <div className="form-group">
-                <label>DeepSeek API Key</label>
+                <label>{currentProvider.name} API Key{provider === 'custom' ? '（可选）' : ''}</label>
                 <div className="input-row">
                   <input 
                     type="password" 
-                    value={deepseekKey} 
-                    onChange={(e) => setDeepseekKey(e.target.value)} 
+                    value={apiKeys[provider] || ''} 
+                    onChange={(e) => setApiKeys({ ...apiKeys, [provider]: e.target.value })} 
                     placeholder="sk-..."
                   />
                   <button 
                     className="test-btn"
-                    onClick={() => handleTest('deepseek', deepseekKey)} 
-                    disabled={loading || !deepseekKey}
+                    onClick={() => handleTest(provider, apiKeys[provider] || '')} 
+                    disabled={loading || (provider === 'custom' ? !customBaseUrl || !customModel : !apiKeys[provider])}
                   >
                     测试
                   </button>
                 </div>
-                <a href="https://platform.deepseek.com/api_keys" target="_blank" className="api-link">
-                  获取 DeepSeek API Key →
-                </a>
+                {currentProvider.keyUrl && (
+                  <a href={currentProvider.keyUrl} target="_blank" className="api-link">
+                    获取{currentProvider.name} API Key →
+                  </a>
+                )}
               </div>
 
-              <div className="form-group">
-                <label>通义千问 API Key</label>
-                <div className="input-row">
+              {selectedModel && (
+                <div className="form-group">
+                  <label>上下文窗口（tokens，超出时自动压缩历史）</label>
                   <input 
-                    type="password" 
-                    value={qwenKey} 
-                    onChange={(e) => setQwenKey(e.target.value)} 
-                    placeholder="sk-..."
+                    type="number" 
+                    step={1024}
+                    value={contextBudgets[selectedModel] ?? ''} 
+                    onChange={(e) => setContextBudget(e.target.value)} 
+                    placeholder={catalogWindow ? `${catalogWindow}` : '32768'}
                   />
-                  <button 
-                    className="test-btn"
-                    onClick={() => handleTest('qwen', qwenKey)} 
-                    disabled={loading || !qwenKey}
-                  >
-                    测试
-                  </button>
                 </div>
-                <a href="https://dashscope.console.aliyun.com/apiKey" target="_blank" className="api-link">
-                  获取通义千问 API Key →
-                </a>
+              )}
+
+              <div className="form-group">
+                <label>备用模型（当前模型重试失败后按顺序切换）</label>
+                {fallbacks.map((f, i) => (
+                  <div key={i} className="input-row fallback-row">
+                    <select 
+                      value={f.provider} 
+                      onChange={(e) => updateFallback(i, { provider: e.target.value, model: null })}
+                    >
+                      {PROVIDERS.map(p => (
+                        <option key={p.id} value={p.id}>{p.name}</option>
+                      ))}
+                    </select>
+                    <input 
+                      type="text" 
+                      value={f.model || ''} 
+                      onChange={(e) => updateFallback(i, { ...f, model: e.target.value || null })} 
+                      placeholder="模型（留空使用该提供商的模型设置）"
+                    />
+                    <button className="test-btn" onClick={() => setFallbacks(fallbacks.filter((_, j) => j !== i))}>
+                      {t('common.delete')}
+                    </button>
+                  </div>
+                ))}
+                <button 
+                  className="add-fallback-btn"
+                  onClick={() => setFallbacks([...fallbacks, { provider: PROVIDERS.find(p => p.id !== provider)?.id || 'qwen', model: null }])}
+                >
+                  + 添加备用模型
+                </button>
+              </div>
+
+              <div className="form-group">
+                <label>生成参数（留空使用模型默认值）</label>
+                <div className="params-grid">
+                  {NUMERIC_PARAMS.map(p => (
+                    <label key={p.key} className="param-field">
+                      <span>{p.label}</span>
+                      <input 
+                        type="number" 
+                        step={p.step}
+                        value={params[p.key] ?? ''} 
+                        onChange={(e) => setParams({ ...params, [p.key]: e.target.value === '' ? null : Number(e.target.value) })} 
+                        placeholder={p.placeholder}
+                      />
+                    </label>
+                  ))}
+                </div>
+                <input 
+                  type="text" 
+                  value={params.stop?.join(', ') || ''} 
+                  onChange={(e) => {
+                    const stop = e.target.value.split(',').map(s => s.trim()).filter(Boolean);
+                    setParams({ ...params, stop: stop.length ? stop : null });
+                  }} 
+                  placeholder="停止序列，用逗号分隔（最多 4 个）"
+                />
+                <label className="checkbox-row">
+                  <input 
+                    type="checkbox" 
+                    checked={params.response_format?.type === 'json_object'} 
+                    onChange={(e) => setParams({ ...params, response_format: e.target.checked ? { type: 'json_object' } : null })} 
+                  />
+                  JSON 模式
+                </label>
               </div>
 
               <div className="settings-actions">
@@ -59,6 +59,9 @@ export const Terminal = forwardRef<TerminalHandle>((_, ref) => {
 
     xtermRef.current = term;
 
+    // Bash calls whose output was already streamed live
+    const streamed = new Set<string>();
+
     // Listen for bash execution events from agent
     const unlistenBash = listen<AgentEvent>('agent-event', (event) => {
       const payload = event.payload;
@@ -81,9 +84,19 @@ export const Terminal = forwardRef<TerminalHandle>((_, ref) => {
         }
       }
       
+      if (payload.type === 'ToolProgress') {
+        streamed.add(payload.content.id);
+        term.write(payload.content.chunk.replace(/\r?\n/g, '\r\n'));
+      }
+
       if (payload.type === 'ToolResult') {
         const content = payload.content;
-        if (content.name === 'bash') {
+        if (content.name === 'bash' && streamed.delete(content.id)) {
+          // Output is already on screen, only show the outcome
+          const isError = content.result.startsWith('Exit Code:') || content.result.includes('Error:');
+          const status = isError ? '\x1b[31m└─ 执行失败' : '\x1b[32m└─ 执行成功';
+          term.writeln(`${status} ─────────────────────────────\x1b[0m`);
+        } else if (content.name === 'bash') {
           // Format output
           const lines = content.result.split('\n');
           const isError = content.result.startsWith('Exit Code:') || content.result.includes('Error:');
@@ -1,13 +1,17 @@
 import { create } from 'zustand';
 import { invoke } from '@tauri-apps/api/core';
-import { Message } from '../types';
+import { Message, PendingApproval, SessionUsage, Usage } from '../types';
 
 interface ChatState {
   messages: Message[];
   loading: boolean;
   streamingContent: string;
+  streamingReasoning: string;
   currentSessionId: string | null;
+  currentTaskId: string | null;
+  pendingApproval: PendingApproval | null;
   sessionRefreshTrigger: number; // Increment to force sidebar refresh
+  usage: SessionUsage | null;
 
   // Sync actions
   setMessages: (messages: Message[]) => void;
@@ -15,8 +19,13 @@ interface ChatState {
   setLoading: (loading: boolean) => void;
   setStreamingContent: (content: string) => void;
   appendStreamingContent: (content: string) => void;
+  setStreamingReasoning: (content: string) => void;
+  appendStreamingReasoning: (content: string) => void;
   setCurrentSessionId: (id: string | null) => void;
+  setCurrentTaskId: (id: string | null) => void;
+  setPendingApproval: (approval: PendingApproval | null) => void;
   triggerSessionRefresh: () => void;
+  addUsage: (usage: Usage, cost: number | null) => void;
   
   // Async actions
   loadSession: (sessionId: string) => Promise<void>;
@@ -27,23 +36,45 @@ export const useChatStore = create<ChatState>((set) => ({
   messages: [],
   loading: false,
   streamingContent: '',
+  streamingReasoning: '',
   currentSessionId: null,
+  currentTaskId: null,
+  pendingApproval: null,
   sessionRefreshTrigger: 0,
+  usage: null,
 
   setMessages: (messages) => set({ messages }),
   addMessage: (message) => set((state) => ({ messages: [...state.messages, message] })),
   setLoading: (loading) => set({ loading }),
   setStreamingContent: (content) => set({ streamingContent: content }),
   appendStreamingContent: (content) => set((state) => ({ streamingContent: state.streamingContent + content })),
+  setStreamingReasoning: (content) => set({ streamingReasoning: content }),
+  appendStreamingReasoning: (content) => set((state) => ({ streamingReasoning: state.streamingReasoning + content })),
   setCurrentSessionId: (id) => set({ currentSessionId: id }),
+  setCurrentTaskId: (id) => set({ currentTaskId: id }),
+  setPendingApproval: (approval) => set({ pendingApproval: approval }),
   triggerSessionRefresh: () => set((state) => ({ sessionRefreshTrigger: state.sessionRefreshTrigger + 1 })),
+  addUsage: (usage, cost) => set((state) => {
+    const total = state.usage || { requests: 0, prompt_tokens: 0, completion_tokens: 0, cached_tokens: 0, cost: 0 };
+    return {
+      usage: {
+        requests: total.requests + 1,
+        prompt_tokens: total.prompt_tokens + usage.prompt_tokens,
+        completion_tokens: total.completion_tokens + usage.completion_tokens,
+        cached_tokens: total.cached_tokens + usage.cached_tokens,
+        cost: total.cost + (cost || 0),
+      },
+    };
+  }),
 
   loadSession: async (sessionId: string) => {
     try {
-      set({ loading: true, streamingContent: '' });
+      set({ loading: true, streamingContent: '', streamingReasoning: '' });
       const sessionMessages = await invoke<Message[]>('get_session_messages', { sessionId });
+      const usage = await invoke<SessionUsage>('get_session_usage', { id: sessionId });
       set({ 
         messages: sessionMessages, 
+        usage, 
         currentSessionId: sessionId, 
         loading: false 
       });
@@ -58,6 +89,8 @@ export const useChatStore = create<ChatState>((set) => ({
       currentSessionId: null, 
       messages: [], 
       streamingContent: '', 
+      streamingReasoning: '',
+      usage: null,
       loading: false 
     });
   }
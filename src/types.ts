@@ -4,6 +4,11 @@ export interface Message {
   tool_calls?: ToolCall[];
   tool_call_id?: string;
   name?: string;
+  reasoning_content?: string;
+  // Model that produced an assistant message; not sent back to the API
+  model?: string;
+  // Status line shown only in the chat (welcome, errors, failover); never saved or sent
+  notice?: boolean;
 }
 
 export interface ToolCall {
@@ -15,14 +20,48 @@ export interface ToolCall {
   };
 }
 
+export interface Usage {
+  prompt_tokens: number;
+  completion_tokens: number;
+  cached_tokens: number;
+}
+
+// Totals returned by get_session_usage; cost is in CNY
+export interface SessionUsage extends Usage {
+  requests: number;
+  cost: number;
+}
+
+// Which limit of TaskBudget stopped a task
+export type BudgetLimit = 'Steps' | 'Time' | 'Tokens' | 'Cost';
+
+export type RiskLevel = 'ReadOnly' | 'Write' | 'Execute';
+
+export interface PendingApproval {
+  request_id: string;
+  name: string;
+  args: string;
+  id: string;
+  risk: RiskLevel;
+}
+
 // Struct matching Rust AgentEvent
 export type AgentEvent = 
+  | { type: 'TaskStarted'; content: { task_id: string } }
   | { type: 'Thinking'; content: string }
   | { type: 'StreamChunk'; content: string }
+  | { type: 'Reasoning'; content: string }
   | { type: 'StreamEnd'; content: null }
   | { type: 'ToolCall'; content: { name: string; args: string; id: string } }
+  | { type: 'ApprovalRequired'; content: PendingApproval }
+  | { type: 'ToolProgress'; content: { id: string; chunk: string } }
   | { type: 'ToolResult'; content: { name: string; result: string; id: string } }
+  | { type: 'Usage'; content: { model: string; usage: Usage; cost: number | null } }
+  | { type: 'HistoryCompacted'; content: { summary: string; turns: number } }
+  | { type: 'BudgetExceeded'; content: BudgetLimit }
   | { type: 'Message'; content: string }
-  | { type: 'NewMessage'; content: Message }
+  | { type: 'ProviderSwitched'; content: { from: string; to: string; reason: string } }
+  | { type: 'NewMessage'; content: { message: Message; model: string | null } }
   | { type: 'Error'; content: string }
+  | { type: 'Cancelled'; content: null }
   | { type: 'Done'; content: null };